@@ -1,29 +1,118 @@
-mod wadd;
-
+use std::collections::BTreeSet;
 use std::env;
-use std::fs::File;
-use std::io::Write;
+use std::fs::{self, File};
+use std::io::{self, BufWriter, IsTerminal, Write};
 use std::process::exit;
 
 use svg::node::element::path::Data;
 use svg::node::element::{Line, Path};
 use svg::Document;
-use wadd::Wad;
-
-use crate::wadd::{LineDef, Sector, Vertex, WadType};
+use wadd_rust::wadd::{
+    music_to_midi, Colormap, Demo, Flat, Lump, MapData, MapFormat, Palette, PcSpeakerSound,
+    Picture, Sound, Sprite, TextureDef, TicCmd, Vertex, Wad, WadError, WadStack, WadType,
+};
 
 fn main() {
     let args: Vec<String> = env::args().collect();
 
-    match args.as_slice() {
-        [_, filename] => handle_command(filename, &String::from("info"), &[]),
-        [_, filename, command, rest @ ..] => handle_command(filename, command, rest),
+    // Like Doom itself, additional PWADs can be loaded on top of the main WAD with `-file`
+    let (args, pwad_filenames) = match args.iter().position(|arg| arg == "-file") {
+        Some(i) => (&args[..i], &args[i + 1..]),
+        None => (&args[..], &[][..]),
+    };
+
+    match args {
+        [_, filename] => handle_command(filename, pwad_filenames, "info", &[]),
+        [_, filename, command, rest @ ..] => {
+            handle_command(filename, pwad_filenames, command, rest)
+        }
         _ => print_usage_and_exit(),
     }
 }
 
-fn handle_command(filename: &String, command: &str, params: &[String]) {
-    let wad = match Wad::open(filename) {
+fn handle_command(filename: &str, pwad_filenames: &[String], command: &str, params: &[String]) {
+    match command {
+        "add" | "replace" | "rm" | "rename" => {
+            if !pwad_filenames.is_empty() {
+                println!("Only one WAD can be edited at a time.");
+                exit(1);
+            }
+            handle_edit_command(filename, command, params)
+        }
+        _ => {
+            let mut filenames = vec![filename];
+            filenames.extend(pwad_filenames.iter().map(String::as_str));
+            let wads = match WadStack::open(&filenames) {
+                Ok(wads) => wads,
+                Err(err) => {
+                    println!("Error reading WAD: {}", err);
+                    exit(1);
+                }
+            };
+            handle_read_command(&wads, command, params)
+        }
+    }
+}
+
+fn handle_read_command(wads: &WadStack, command: &str, params: &[String]) {
+    match command {
+        "info" => {
+            for wad in &wads.wads {
+                show_info(wad)
+            }
+        }
+        "maps" => list_maps(wads),
+        "svg" => match params.first() {
+            Some(map_name) => match wads.map(map_name) {
+                Some(map) => extract_map(map),
+                None => {
+                    println!("That map does not exist.");
+                    exit(1);
+                }
+            },
+            None => {
+                println!("Dumping all maps...");
+                for map in wads.maps() {
+                    extract_map(map)
+                }
+            }
+        },
+        "extract-palettes" => {
+            let palettes = or_exit_reading(wads.palettes());
+            for (i, palette) in palettes.iter().enumerate() {
+                extract_palette(palette, &format!("PLAYPAL{}.png", i));
+            }
+            if let Some(palette) = palettes.first() {
+                extract_colormap(&or_exit_reading(wads.colormap()), palette);
+            }
+        }
+        "extract-gfx" => extract_gfx(wads, params),
+        "extract-flats" => extract_flats(wads, params),
+        "extract-textures" => extract_textures(wads, params),
+        "extract-sprites" => extract_sprites(wads, params),
+        "extract-sounds" => extract_sounds(wads, params),
+        "extract-music" => extract_music(wads, params),
+        "extract-genmidi" => extract_genmidi(wads),
+        "demo" => show_demos(wads, params),
+        "demo-tics" => match params {
+            [name] => dump_demo_tics(&read_demo(wads, name)),
+            _ => print_usage_and_exit(),
+        },
+        "extract-endoom" => {
+            let endoom = or_exit_reading(wads.endoom());
+            write_file("ENDOOM.txt", endoom.to_text().as_bytes());
+            write_file("ENDOOM.ans", endoom.to_ansi().as_bytes());
+            write_file("ENDOOM.html", endoom.to_html("ENDOOM").as_bytes());
+        }
+        _ => {
+            println!("Sorry, I don't know how to {}.", command);
+            std::process::exit(1);
+        }
+    }
+}
+
+fn handle_edit_command(filename: &str, command: &str, params: &[String]) {
+    let mut wad = match Wad::open(filename) {
         Ok(wad) => wad,
         Err(err) => {
             println!("Error reading WAD: {}", err);
@@ -32,16 +121,37 @@ fn handle_command(filename: &String, command: &str, params: &[String]) {
     };
 
     match command {
-        "info" => show_info(&wad),
-        "maps" => list_maps(&wad),
-        "svg" => match params.first() {
-            Some(map_name) => extract_map(&wad, &map_name),
-            None => {
-                println!("Dumping all maps...");
-                for map in &wad.maps {
-                    extract_map(&wad, &map.name)
-                }
+        "add" => match params {
+            [lump_name, data_filename] => add_lump(wad, filename, lump_name, data_filename, None),
+            [lump_name, data_filename, namespace] => {
+                add_lump(wad, filename, lump_name, data_filename, Some(namespace))
+            }
+            _ => print_usage_and_exit(),
+        },
+        "replace" => match params {
+            [lump_name, data_filename] => {
+                let index = find_lump_index_or_exit(&wad, lump_name);
+                let data = read_file_or_exit(data_filename);
+                or_exit(wad.replace_lump(index, data));
+                save_in_place(wad, filename);
+            }
+            _ => print_usage_and_exit(),
+        },
+        "rm" => match params {
+            [lump_name] => {
+                let index = find_lump_index_or_exit(&wad, lump_name);
+                or_exit(wad.remove_lump(index));
+                save_in_place(wad, filename);
+            }
+            _ => print_usage_and_exit(),
+        },
+        "rename" => match params {
+            [lump_name, new_name] => {
+                let index = find_lump_index_or_exit(&wad, lump_name);
+                or_exit(wad.rename_lump(index, &new_name.to_ascii_uppercase()));
+                save_in_place(wad, filename);
             }
+            _ => print_usage_and_exit(),
         },
         _ => {
             println!("Sorry, I don't know how to {}.", command);
@@ -50,12 +160,8 @@ fn handle_command(filename: &String, command: &str, params: &[String]) {
     }
 }
 
-fn extract_map(wad: &Wad, map_name: &str) {
-    let map = wad
-        .maps
-        .iter()
-        .find(|map| map.name == map_name)
-        .expect("That map does not exist.");
+fn extract_map(map: &MapData) {
+    let map_name = &map.name;
 
     let lines: Vec<(Vertex, Vertex)> = map
         .linedefs
@@ -68,7 +174,7 @@ fn extract_map(wad: &Wad, map_name: &str) {
         })
         .collect();
 
-    assert!(lines.len() > 0);
+    assert!(!lines.is_empty());
 
     // Figure out which offsets to use to put the map in the top left corner
     let min_x: i16 = lines
@@ -97,69 +203,11 @@ fn extract_map(wad: &Wad, map_name: &str) {
     let height = max_y - min_y;
     let width = max_x - min_x;
 
-    // Get the sectors
-    type SectorLine = (Vertex, Vertex, LineDef);
-    #[derive(Debug)]
-    struct RenderableSector {
-        lines: Vec<SectorLine>,
-        sector: Sector,
-    }
-    let sectors: Vec<RenderableSector> = map
-        .sectors
-        .iter()
-        .enumerate()
-        .map(|(sector_index, sector)| {
-            let sector_lines: Vec<LineDef> = map
-                .linedefs
-                .iter()
-                .filter(|linedef| {
-                    if linedef.sidedef_right >= 0 {
-                        let sidedef_right = map.sidedefs[linedef.sidedef_right as usize].clone();
-                        if (sidedef_right.sector as usize) == sector_index {
-                            return true;
-                        }
-                    }
-                    if linedef.sidedef_left >= 0 {
-                        let sidedef_left = map.sidedefs[linedef.sidedef_left as usize].clone();
-                        if (sidedef_left.sector as usize) == sector_index {
-                            return true;
-                        }
-                    }
-                    false
-                })
-                .map(|linedef| linedef.clone())
-                .collect();
-            let vertex_lines: Vec<SectorLine> = sector_lines
-                .iter()
-                .map(|linedef| {
-                    let v1 = map.vertexes[linedef.vertex_begin as usize];
-                    let v2 = map.vertexes[linedef.vertex_end as usize];
-
-                    // Vertexes are stored upside down from what we'd expect, so flip their y coordinate
-                    let v1 = Vertex {
-                        y: max_y - (v1.y - min_y),
-                        ..v1
-                    };
-                    let v2 = Vertex {
-                        y: max_y - (v2.y - min_y),
-                        ..v2
-                    };
-
-                    (v1, v2, linedef.clone())
-                })
-                .collect();
-            RenderableSector {
-                lines: vertex_lines,
-                sector: sector.clone(),
-            }
-        })
-        .collect();
-
     // Write it out
     println!(
         "{} has {} sectors and is {}x{}",
         &map_name,
-        sectors.len(),
+        map.sectors.len(),
         width,
         height
     );
@@ -168,74 +216,39 @@ fn extract_map(wad: &Wad, map_name: &str) {
     // Debugging feature: only show a particular subset of sectors; leave empty to include all
     // sectors (as usual).
     let debugging_sector_filter: Vec<usize> = vec![];
+    let show_sector = |sector_id: usize| {
+        debugging_sector_filter.is_empty() || debugging_sector_filter.contains(&sector_id)
+    };
 
-    for (sector_id, sector) in sectors.iter().enumerate() {
-        if !debugging_sector_filter.is_empty() && !debugging_sector_filter.contains(&sector_id) {
+    for (sector_id, sector) in map.sectors.iter().enumerate() {
+        if !show_sector(sector_id) {
             continue;
         }
-        println!("\nSector {} has {} lines:", sector_id, sector.lines.len());
-        for (from_v, to_v, _) in &sector.lines {
-            println!(
-                "({}, {}) -> ({}, {})",
-                from_v.x + offset_x,
-                from_v.y + offset_y,
-                to_v.x + offset_x,
-                to_v.y + offset_y
-            );
-        }
 
+        // Sectors can be made of several separate areas, each of which can have holes where other
+        // sectors sit inside them, so each polygon's rings are added to the same path and the holes
+        // are cut out by their opposite winding
+        let polygons = map.sector_polygons(sector_id);
+        for diagnostic in &polygons.diagnostics {
+            println!("WARNING: Sector {}: {}", sector_id, diagnostic);
+        }
         let mut data = Data::new();
-        let mut pending_lines = sector.lines.clone();
-        while !pending_lines.is_empty() {
-            // Sectors consist of a series of lines that may or may not all connect with each other:
-            // a sector might just be a basic polygon, but it could also have a donut-like shape with
-            // an empty spot or another sector contained within it. Because of this, we can't just
-            // just iterate over the lines in the sector and add them to a single Path. Instead, we
-            // create a Path, pop the next line off the list, and walk through all of the remaining
-            // lines until we close the path. We continue this until all lines have been added to a
-            // path.
-            // TODO: There is currently something wrong with this logic, leading to orphan lines not
-            // getting added to the path. Sector 0 of E4M9 demonstrates this pretty well.
-            if pending_lines.len() < 2 {
-                println!(
-                    "WARNING: Sector {} only has {} lines left",
-                    sector_id,
-                    pending_lines.len()
-                );
-            }
-
-            let (initial_v, mut to_v, _) = pending_lines.remove(0);
-
-            data = data
-                .move_to((initial_v.x + offset_x, initial_v.y + offset_y))
-                .line_to((to_v.x + offset_x, to_v.y + offset_y));
-
-            while let Some(next_line_idx) =
-                pending_lines
-                    .iter()
-                    .position(|(other_from_v, other_to_v, _)| {
-                        // Look for any other lines that share our `to_v` vertex, regardless of direction
-                        other_from_v == &to_v || other_to_v == &to_v // handle backwards lines, e.g. E1M1's sector 2
-                    })
-            {
-                let (next_from_v, next_to_v, _) = pending_lines.remove(next_line_idx);
-                if next_to_v == to_v {
-                    // This line points in the opposite direction that we want, so swap from_v <-> to_v
-                    to_v = next_from_v
-                } else {
-                    to_v = next_to_v
+        for polygon in &polygons.polygons {
+            for ring in std::iter::once(&polygon.outer).chain(&polygon.holes) {
+                for (i, v) in ring.iter().enumerate() {
+                    // Vertexes are stored upside down from what we'd expect, so flip their y coordinate
+                    let point = (v.x + offset_x, max_y - (v.y - min_y) + offset_y);
+                    data = match i {
+                        0 => data.move_to(point),
+                        _ => data.line_to(point),
+                    };
                 }
-
-                data = data.line_to((to_v.x + offset_x, to_v.y + offset_y));
+                data = data.close();
             }
-            data = data.close();
         }
 
-        let light_level = sector.sector.light_level.clamp(0, 255);
+        let light_level = sector.light_level.clamp(0, 255);
         let fill_color = format!("rgb({}, {}, {})", light_level, light_level, light_level);
-        // TODO: eventually it'd be nice to have each individual line be colored differently depending
-        // on whether it was one-sided or two-sided. Doing this would require drawing the path without
-        // a stroke, and drawing all of the lines individually.
         let path = Path::new()
             .set("id", format!("sector{}", sector_id))
             .set("fill", fill_color)
@@ -245,32 +258,41 @@ fn extract_map(wad: &Wad, map_name: &str) {
         doc = doc.add(path);
     }
 
-    // Draw sector lines; we do this as a separate pass at the end so they are drawn on top of the
+    // Draw the lines; we do this as a separate pass at the end so they are drawn on top of the
     // filled paths.
-    for (sector_id, sector) in sectors.iter().enumerate() {
-        if !debugging_sector_filter.is_empty() && !debugging_sector_filter.contains(&sector_id) {
+    let side_sector = |sidedef: i16| {
+        usize::try_from(sidedef)
+            .ok()
+            .and_then(|sidedef| map.sidedefs.get(sidedef))
+            .map(|sidedef| sidedef.sector as usize)
+    };
+    for linedef in &map.linedefs {
+        let sectors = [
+            side_sector(linedef.sidedef_right),
+            side_sector(linedef.sidedef_left),
+        ];
+        if !sectors.into_iter().flatten().any(show_sector) {
             continue;
         }
-        for (from_v, to_v, linedef) in sector.lines.iter() {
-            let mut line = Line::new()
-                .set("x1", from_v.x + offset_x)
-                .set("y1", from_v.y + offset_y)
-                .set("x2", to_v.x + offset_x)
-                .set("y2", to_v.y + offset_y);
-            if linedef.sidedef_left < 0 || linedef.sidedef_right < 0 {
-                // one-sided line
-                line = line.set("stroke", "red").set("stroke-width", "2");
-            } else {
-                // two-sided line
-                line = line
-                    .set("stroke", "rgba(255, 0, 0, 0.25)")
-                    .set("stroke-width", "1");
-            }
-            doc = doc.add(line);
+
+        // Vertexes are stored upside down from what we'd expect, so flip their y coordinate
+        let from_v = map.vertexes[linedef.vertex_begin as usize];
+        let to_v = map.vertexes[linedef.vertex_end as usize];
+        let mut line = Line::new()
+            .set("x1", from_v.x + offset_x)
+            .set("y1", max_y - (from_v.y - min_y) + offset_y)
+            .set("x2", to_v.x + offset_x)
+            .set("y2", max_y - (to_v.y - min_y) + offset_y);
+        if linedef.sidedef_left < 0 || linedef.sidedef_right < 0 {
+            // one-sided line
+            line = line.set("stroke", "red").set("stroke-width", "2");
+        } else {
+            // two-sided line
+            line = line
+                .set("stroke", "rgba(255, 0, 0, 0.25)")
+                .set("stroke-width", "1");
         }
-    }
-    for line in sectors[0].lines.clone() {
-        println!("{:?}", line);
+        doc = doc.add(line);
     }
 
     // Save as SVG
@@ -298,7 +320,7 @@ fn extract_map(wad: &Wad, map_name: &str) {
     {}
 </body>
 "#,
-        doc.to_string()
+        doc
     );
 
     let filename = format!("{}.html", &map_name);
@@ -309,17 +331,628 @@ fn extract_map(wad: &Wad, map_name: &str) {
     }
 }
 
-fn list_maps(wad: &Wad) {
-    println!("{} maps:", wad.maps.len());
-    for map in &wad.maps {
+// Each palette is drawn as a 16x16 grid of swatches, in index order
+fn extract_palette(palette: &Palette, filename: &str) {
+    const SWATCH_SIZE: usize = 16;
+    let size = 16 * SWATCH_SIZE;
+    let mut rgba = Vec::with_capacity(size * size * 4);
+    for y in 0..size {
+        for x in 0..size {
+            let index = (y / SWATCH_SIZE) * 16 + x / SWATCH_SIZE;
+            rgba.extend_from_slice(&palette.rgba(index as u8));
+        }
+    }
+    write_png(filename, size as u32, size as u32, &rgba);
+}
+
+// Each of the colormap's tables is drawn as a row of 256 swatches, from the brightest table at the
+// top down to the invulnerability and all-black tables at the bottom
+fn extract_colormap(colormap: &Colormap, palette: &Palette) {
+    const SWATCH_WIDTH: usize = 4;
+    const SWATCH_HEIGHT: usize = 8;
+    let width = 256 * SWATCH_WIDTH;
+    let height = colormap.tables.len() * SWATCH_HEIGHT;
+    let mut rgba = Vec::with_capacity(width * height * 4);
+    for y in 0..height {
+        let table = &colormap.tables[y / SWATCH_HEIGHT];
+        for x in 0..width {
+            rgba.extend_from_slice(&palette.rgba(table[x / SWATCH_WIDTH]));
+        }
+    }
+    write_png("COLORMAP.png", width as u32, height as u32, &rgba);
+}
+
+// Writes the given picture lumps to [lump name].png, or every lump that can be read as a picture if
+// none are given. Flats aren't in the picture format, so they're left out.
+fn extract_gfx(wads: &WadStack, lump_names: &[String]) {
+    let palette = first_palette_or_exit(wads);
+
+    if !lump_names.is_empty() {
+        for lump_name in lump_names {
+            let Some(lump) = wads.find_lump(lump_name) else {
+                println!("There is no {} lump.", lump_name);
+                exit(1);
+            };
+            let picture =
+                or_exit_reading(Picture::decode(lump.name(), &or_exit_reading(lump.bytes())));
+            write_picture(&picture, &palette, &format!("{}.png", lump.name()));
+        }
+        return;
+    }
+
+    // Later WADs are extracted last, so their pictures replace any with the same name
+    let mut count = 0;
+    for wad in &wads.wads {
+        let flats: Vec<usize> = wad
+            .namespace_lumps("F")
+            .iter()
+            .map(|lump| lump.index)
+            .collect();
+        for lump in wad.lumps() {
+            if lump.entry.size == 0 || flats.contains(&lump.index) {
+                continue;
+            }
+            let picture = lump
+                .bytes()
+                .and_then(|bytes| Picture::decode(lump.name(), &bytes));
+            if let Ok(picture) = picture {
+                write_picture(&picture, &palette, &format!("{}.png", lump.name()));
+                count += 1;
+            }
+        }
+    }
+    println!("Extracted {} pictures.", count);
+}
+
+// Writes the given flats to [flat name].png, or every flat if none are given
+fn extract_flats(wads: &WadStack, flat_names: &[String]) {
+    let palette = first_palette_or_exit(wads);
+
+    if !flat_names.is_empty() {
+        for flat_name in flat_names {
+            let flat = or_exit_reading(wads.flat(flat_name));
+            write_flat(
+                &flat,
+                &palette,
+                &format!("{}.png", flat_name.to_ascii_uppercase()),
+            );
+        }
+        return;
+    }
+
+    let flats = wads.namespace_lumps("F");
+    for lump in &flats {
+        let flat = or_exit_reading(
+            lump.bytes()
+                .and_then(|bytes| Flat::decode(lump.name(), &bytes)),
+        );
+        write_flat(&flat, &palette, &format!("{}.png", lump.name()));
+    }
+    println!("Extracted {} flats.", flats.len());
+
+    for animation in or_exit_reading(wads.flat_animations()) {
+        println!(
+            "- animated every {} tics: {}",
+            animation.tics_per_frame,
+            animation.frames.join(", ")
+        );
+    }
+}
+
+// Writes the given wall textures to [texture name].png, or every texture if none are given, and
+// lists any that are missing patches
+fn extract_textures(wads: &WadStack, texture_names: &[String]) {
+    let palette = first_palette_or_exit(wads);
+    let textures = or_exit_reading(wads.textures());
+
+    let selected: Vec<&TextureDef> = if texture_names.is_empty() {
+        textures.textures.iter().collect()
+    } else {
+        texture_names
+            .iter()
+            .map(|name| {
+                textures.texture(name).unwrap_or_else(|| {
+                    println!("There is no {} texture.", name);
+                    exit(1);
+                })
+            })
+            .collect()
+    };
+
+    let mut broken = 0;
+    for texture in &selected {
+        let composed = or_exit_reading(wads.compose_texture(&textures, texture));
+        if !composed.missing_patches.is_empty() {
+            println!(
+                "- {} is missing patches: {}",
+                texture.name,
+                composed.missing_patches.join(", ")
+            );
+            broken += 1;
+        }
+        write_picture(
+            &composed.picture,
+            &palette,
+            &format!("{}.png", texture.name),
+        );
+    }
+    println!(
+        "Extracted {} textures, {} with missing patches.",
+        selected.len(),
+        broken
+    );
+}
+
+// Writes a contact sheet of every frame and rotation of the given sprites to [sprite name].png, or
+// of every sprite if none are given, then lists any things in the maps whose sprites are missing
+fn extract_sprites(wads: &WadStack, sprite_names: &[String]) {
+    let palette = first_palette_or_exit(wads);
+    let sprites = wads.sprites();
+
+    let selected: Vec<&Sprite> = if sprite_names.is_empty() {
+        sprites.sprites.iter().collect()
+    } else {
+        sprite_names
+            .iter()
+            .map(|name| {
+                sprites.sprite(name).unwrap_or_else(|| {
+                    println!("There is no {} sprite.", name);
+                    exit(1);
+                })
+            })
+            .collect()
+    };
+
+    for sprite in &selected {
+        let sheet = or_exit_reading(wads.sprite_sheet(sprite));
+        write_picture(&sheet, &palette, &format!("{}.png", sprite.name));
+    }
+    println!("Extracted {} sprites.", selected.len());
+
+    if !sprites.invalid_lumps.is_empty() {
+        println!(
+            "\nThese lumps are in the sprite namespace, but aren't named like sprites: {}",
+            sprites.invalid_lumps.join(", ")
+        );
+    }
+    for map in wads.maps() {
+        let missing = sprites.missing_sprites(map);
+        if missing.is_empty() {
+            continue;
+        }
+        println!("\n{} has things whose sprites are missing:", map.name);
+        for missing_sprite in missing {
+            let thing = &map.things[missing_sprite.thing];
+            println!(
+                "- thing {} (type {}) at ({}, {}) needs {}",
+                missing_sprite.thing,
+                missing_sprite.thing_type,
+                thing.x,
+                thing.y,
+                missing_sprite.sprite
+            );
+        }
+    }
+}
+
+// Writes the given sound lumps to [lump name].wav, or every DS* and DP* lump if none are given.
+// The PC speaker sounds (DP*) are synthesized, unless -nopcspeaker is given to leave them out.
+fn extract_sounds(wads: &WadStack, params: &[String]) {
+    let pc_speaker = !params.iter().any(|param| param == "-nopcspeaker");
+    let lump_names: Vec<&String> = params
+        .iter()
+        .filter(|param| *param != "-nopcspeaker")
+        .collect();
+    let is_pc_speaker = |lump: &Lump| lump.name().to_ascii_uppercase().starts_with("DP");
+
+    if !lump_names.is_empty() {
+        for lump_name in lump_names {
+            let Some(lump) = wads.find_lump(lump_name) else {
+                println!("There is no {} lump.", lump_name);
+                exit(1);
+            };
+            if !pc_speaker && is_pc_speaker(&lump) {
+                println!(
+                    "Skipping {}, since PC speaker sounds are turned off",
+                    lump.name()
+                );
+                continue;
+            }
+            let wav = or_exit_reading(sound_to_wav(&lump));
+            write_file(&format!("{}.wav", lump.name()), &wav);
+        }
+        return;
+    }
+
+    let mut count = 0;
+    let mut lump_names = lump_names_with_prefix(wads, "DS");
+    if pc_speaker {
+        lump_names.extend(lump_names_with_prefix(wads, "DP"));
+    }
+    for lump_name in lump_names {
+        let lump = wads.find_lump(&lump_name).unwrap();
+        match sound_to_wav(&lump) {
+            Ok(wav) => {
+                write_file(&format!("{}.wav", lump.name()), &wav);
+                count += 1;
+            }
+            Err(err) => println!("Skipping {}", err),
+        }
+    }
+    println!("Extracted {} sounds.", count);
+}
+
+fn sound_to_wav(lump: &Lump) -> Result<Vec<u8>, WadError> {
+    const PC_SPEAKER_SAMPLE_RATE: u32 = 44100;
+
+    let bytes = lump.bytes()?;
+    if lump.name().to_ascii_uppercase().starts_with("DP") {
+        Ok(PcSpeakerSound::decode(lump.name(), &bytes)?.to_wav(PC_SPEAKER_SAMPLE_RATE))
+    } else {
+        Ok(Sound::decode(lump.name(), &bytes)?.to_wav())
+    }
+}
+
+// Converts the given music lumps to MIDI and writes them to [lump name].mid, or every D_* lump if
+// none are given. Music that's already MIDI is written out as-is.
+fn extract_music(wads: &WadStack, lump_names: &[String]) {
+    if !lump_names.is_empty() {
+        for lump_name in lump_names {
+            let Some(lump) = wads.find_lump(lump_name) else {
+                println!("There is no {} lump.", lump_name);
+                exit(1);
+            };
+            let midi = or_exit_reading(
+                lump.bytes()
+                    .and_then(|bytes| music_to_midi(lump.name(), &bytes)),
+            );
+            write_file(&format!("{}.mid", lump.name()), &midi);
+        }
+        return;
+    }
+
+    let mut count = 0;
+    for lump_name in lump_names_with_prefix(wads, "D_") {
+        let lump = wads.find_lump(&lump_name).unwrap();
+        match lump
+            .bytes()
+            .and_then(|bytes| music_to_midi(lump.name(), &bytes))
+        {
+            Ok(midi) => {
+                write_file(&format!("{}.mid", lump.name()), &midi);
+                count += 1;
+            }
+            Err(err) => println!("Skipping {}", err),
+        }
+    }
+    println!("Extracted {} songs.", count);
+}
+
+// Writes the OPL instrument bank as an OP2 bank, a JSON dump, and an SBI file for each instrument
+fn extract_genmidi(wads: &WadStack) {
+    let genmidi = or_exit_reading(wads.genmidi());
+    write_file("GENMIDI.op2", &genmidi.encode());
+    write_file("GENMIDI.json", genmidi.to_json().as_bytes());
+
+    if let Err(err) = fs::create_dir_all("GENMIDI") {
+        println!("Error creating GENMIDI directory: {}", err);
+        exit(1);
+    }
+    for (index, instrument) in genmidi.instruments.iter().enumerate() {
+        write_file(&format!("GENMIDI/{:03}.sbi", index), &instrument.to_sbi());
+    }
+    println!("Extracted {} instruments.", genmidi.instruments.len());
+}
+
+// Summarizes the given demos, each either a lump or an .lmp file; if none are given, every DEMO*
+// lump is summarized
+fn show_demos(wads: &WadStack, names: &[String]) {
+    let names: Vec<String> = if names.is_empty() {
+        lump_names_with_prefix(wads, "DEMO").into_iter().collect()
+    } else {
+        names.to_vec()
+    };
+    for (i, name) in names.iter().enumerate() {
+        if i > 0 {
+            println!();
+        }
+        show_demo(wads, name, &read_demo(wads, name));
+    }
+}
+
+fn read_demo(wads: &WadStack, name: &str) -> Demo {
+    if wads.find_lump(name).is_some() {
+        return or_exit_reading(wads.demo(name));
+    }
+    match fs::read(name) {
+        Ok(bytes) => or_exit_reading(Demo::decode(name, &bytes)),
+        Err(err) => {
+            println!(
+                "There is no {} lump, and it can't be read as a file: {}",
+                name, err
+            );
+            exit(1);
+        }
+    }
+}
+
+fn show_demo(wads: &WadStack, name: &str, demo: &Demo) {
+    const SKILLS: [&str; 5] = [
+        "I'm too young to die",
+        "Hey, not too rough",
+        "Hurt me plenty",
+        "Ultra-Violence",
+        "Nightmare!",
+    ];
+
+    match demo.version {
+        Some(version) => println!("{}: version {} demo", name, version),
+        None => println!("{}: demo from before Doom 1.4", name),
+    }
+    match wads.demo_map(demo) {
+        Some(map) => println!("- map: {}", map.name),
+        None => println!(
+            "- map: {} (not in the loaded WADs)",
+            demo.map_names().join(" or ")
+        ),
+    }
+    println!(
+        "- skill: {}",
+        SKILLS.get(demo.skill as usize).unwrap_or(&"unknown")
+    );
+    let mode = match demo.deathmatch {
+        0 => "cooperative",
+        1 => "deathmatch",
+        _ => "altdeath",
+    };
+    let mut flags = vec![];
+    for (set, flag) in [
+        (demo.respawn, "-respawn"),
+        (demo.fast, "-fast"),
+        (demo.no_monsters, "-nomonsters"),
+    ] {
+        if set {
+            flags.push(flag);
+        }
+    }
+    if flags.is_empty() {
+        println!("- mode: {}", mode);
+    } else {
+        println!("- mode: {} with {}", mode, flags.join(" "));
+    }
+    println!(
+        "- length: {} tics ({:.2} seconds)",
+        demo.tics.len(),
+        demo.duration_seconds()
+    );
+    // Players are numbered from 1, the way the game shows them
+    let players: Vec<String> = demo
+        .player_numbers()
+        .iter()
+        .map(|number| (number + 1).to_string())
+        .collect();
+    println!(
+        "- players: {} (viewed from player {})",
+        players.join(", "),
+        demo.console_player + 1
+    );
+}
+
+// One line per tic, with each player's movement, turning and buttons
+fn dump_demo_tics(demo: &Demo) {
+    const WEAPONS: [&str; 8] = [
+        "fist",
+        "pistol",
+        "shotgun",
+        "chaingun",
+        "rocket launcher",
+        "plasma rifle",
+        "BFG 9000",
+        "chainsaw",
+    ];
+
+    let players = demo.player_numbers();
+    for (tic, cmds) in demo.tics.iter().enumerate() {
+        let cmds: Vec<String> = players
+            .iter()
+            .zip(cmds)
+            .map(|(number, cmd)| {
+                let mut buttons = String::new();
+                if cmd.buttons & TicCmd::SPECIAL != 0 {
+                    buttons.push_str(" special");
+                } else {
+                    if cmd.buttons & TicCmd::ATTACK != 0 {
+                        buttons.push_str(" attack");
+                    }
+                    if cmd.buttons & TicCmd::USE != 0 {
+                        buttons.push_str(" use");
+                    }
+                    if let Some(weapon) = cmd.weapon_change() {
+                        buttons.push_str(&format!(" {}", WEAPONS[weapon as usize]));
+                    }
+                }
+                format!(
+                    "P{}: forward {} side {} turn {}{}",
+                    number + 1,
+                    cmd.forward_move,
+                    cmd.side_move,
+                    cmd.angle_turn,
+                    buttons
+                )
+            })
+            .collect();
+        println!("{}\t{}", tic, cmds.join("\t"));
+    }
+}
+
+// The names of every lump in any of the WADs that start with the given prefix, once each
+fn lump_names_with_prefix(wads: &WadStack, prefix: &str) -> BTreeSet<String> {
+    wads.wads
+        .iter()
+        .flat_map(|wad| &wad.directory)
+        .filter(|entry| entry.name.to_ascii_uppercase().starts_with(prefix))
+        .map(|entry| entry.name.to_ascii_uppercase())
+        .collect()
+}
+
+fn write_file(filename: &str, data: &[u8]) {
+    if let Err(err) = fs::write(filename, data) {
+        println!("Error writing {}: {}", filename, err);
+        exit(1);
+    }
+}
+
+fn write_flat(flat: &Flat, palette: &Palette, filename: &str) {
+    write_png(
+        filename,
+        flat.width as u32,
+        flat.height as u32,
+        &flat.to_rgba(palette),
+    );
+}
+
+fn first_palette_or_exit(wads: &WadStack) -> Palette {
+    or_exit_reading(wads.palettes())
+        .into_iter()
+        .next()
+        .unwrap_or_else(|| {
+            println!("PLAYPAL has no palettes in it.");
+            exit(1);
+        })
+}
+
+fn write_picture(picture: &Picture, palette: &Palette, filename: &str) {
+    write_png(
+        filename,
+        picture.width as u32,
+        picture.height as u32,
+        &picture.to_rgba(palette),
+    );
+}
+
+fn write_png(filename: &str, width: u32, height: u32, rgba: &[u8]) {
+    let result = File::create(filename)
+        .map_err(png::EncodingError::from)
+        .and_then(|file| {
+            let mut encoder = png::Encoder::new(BufWriter::new(file), width, height);
+            encoder.set_color(png::ColorType::Rgba);
+            encoder.set_depth(png::BitDepth::Eight);
+            encoder.write_header()?.write_image_data(rgba)
+        });
+    if let Err(err) = result {
+        println!("Error writing {}: {}", filename, err);
+        exit(1);
+    }
+}
+
+fn add_lump(
+    mut wad: Wad,
+    filename: &str,
+    lump_name: &str,
+    data_filename: &str,
+    namespace: Option<&String>,
+) {
+    let lump_name = lump_name.to_ascii_uppercase();
+    let data = read_file_or_exit(data_filename);
+    match namespace {
+        Some(namespace) => {
+            or_exit(wad.insert_in_namespace(&namespace.to_ascii_uppercase(), &lump_name, data))
+        }
+        None => or_exit(wad.append_lump(&lump_name, data)),
+    };
+    save_in_place(wad, filename);
+}
+
+fn find_lump_index_or_exit(wad: &Wad, lump_name: &str) -> usize {
+    match wad.find_lump_index(lump_name) {
+        Some(index) => index,
+        None => {
+            println!("There is no {} lump in this WAD.", lump_name);
+            exit(1);
+        }
+    }
+}
+
+fn read_file_or_exit(filename: &str) -> Vec<u8> {
+    match fs::read(filename) {
+        Ok(data) => data,
+        Err(err) => {
+            println!("Error reading {}: {}", filename, err);
+            exit(1);
+        }
+    }
+}
+
+fn or_exit_reading<T>(result: Result<T, WadError>) -> T {
+    match result {
+        Ok(value) => value,
+        Err(err) => {
+            println!("Error reading WAD: {}", err);
+            exit(1);
+        }
+    }
+}
+
+fn or_exit<T>(result: Result<T, WadError>) -> T {
+    match result {
+        Ok(value) => value,
+        Err(err) => {
+            println!("Error editing WAD: {}", err);
+            exit(1);
+        }
+    }
+}
+
+// Lump data is read from the original file as it's written out, so the new WAD goes to a temporary
+// file that then replaces the original
+fn save_in_place(wad: Wad, filename: &str) {
+    let temp_filename = format!("{}.tmp", filename);
+    let result = File::create(&temp_filename)
+        .map_err(WadError::from)
+        .and_then(|file| wad.save(BufWriter::new(file)));
+    drop(wad);
+
+    if let Err(err) = result.and_then(|_| Ok(fs::rename(&temp_filename, filename)?)) {
+        println!("Error writing WAD: {}", err);
+        let _ = fs::remove_file(&temp_filename);
+        exit(1);
+    }
+}
+
+fn list_maps(wads: &WadStack) {
+    let maps = wads.maps();
+    println!("{} maps:", maps.len());
+    for map in maps {
+        let format = match map.format {
+            MapFormat::Doom => "Doom",
+            MapFormat::Hexen { .. } => "Hexen",
+            MapFormat::Udmf(_) => "UDMF",
+        };
         println!(
-            "- {} ({} linedefs, {} sectors, {} things, {} vertexes)",
+            "- {} ({} format, {} linedefs, {} sectors, {} things, {} vertexes)",
             map.name,
+            format,
             map.linedefs.len(),
             map.sectors.len(),
             map.things.len(),
             map.vertexes.len()
         );
+        if let Some(diagnostic) = &map.reject.diagnostic {
+            println!("  {}", diagnostic);
+        }
+    }
+    for wad in &wads.wads {
+        print_map_errors(wad);
+    }
+}
+
+fn print_map_errors(wad: &Wad) {
+    if wad.map_errors().is_empty() {
+        return;
+    }
+    println!("\n{} maps could not be read:", wad.map_errors().len());
+    for err in wad.map_errors() {
+        println!("- {}", err);
     }
 }
 
@@ -343,22 +976,119 @@ fn show_info(wad: &Wad) {
             println!("- {: <8}\t(empty lump)", d.name);
         }
     }
+
+    // The details are left to the `maps` command
+    if !wad.map_errors().is_empty() {
+        println!(
+            "\n{} maps could not be read; run the maps command for details.",
+            wad.map_errors().len()
+        );
+    }
+
+    // A PWAD's exit screen is part of its presentation, so show it off
+    if matches!(wad.wad_type, WadType::PWAD) && wad.find_lump("ENDOOM").is_some() {
+        match wad.endoom() {
+            Ok(endoom) if io::stdout().is_terminal() => print!("\n{}", endoom.to_ansi()),
+            Ok(endoom) => print!("\n{}", endoom.to_text()),
+            Err(err) => println!("\nCouldn't show ENDOOM: {}", err),
+        }
+    }
 }
 
 fn print_usage_and_exit() {
     let args: Vec<String> = env::args().collect();
     let executable = args.first().unwrap();
-    println!("usage: {} /path/to/a/doom.wad [command]", executable);
+    println!(
+        "usage: {} /path/to/a/doom.wad [command] [-file /path/to/a/pwad.wad ...]",
+        executable
+    );
     println!("\nAvailable commands:");
     println!("- info");
     println!("  prints info about the WAD. This is the default if a command is not specified.");
     println!("- maps");
-    println!("  prints a list of the maps in the WAD.");
+    println!("  prints a list of the maps in the WAD, and any that can't be read.");
     println!("- svg [map name]");
     println!("  extracts the given map to an SVG file in the current directory with the filename [map name].svg");
     println!(
         "  (if no map name is specified, every map in the WAD will be extracted automatically)"
     );
+    println!("- extract-palettes");
+    println!(
+        "  writes each palette in PLAYPAL to PLAYPAL[number].png as a grid of swatches, and the"
+    );
+    println!("  light level tables in COLORMAP to COLORMAP.png");
+    println!("- extract-gfx [lump name ...]");
+    println!(
+        "  writes the given graphics to [lump name].png; if no lumps are given, every lump in the"
+    );
+    println!("  Doom picture format is extracted");
+    println!("- extract-flats [flat name ...]");
+    println!("  writes the given flats to [flat name].png; if no flats are given, every flat is");
+    println!("  extracted and the animated ones are listed");
+    println!("- extract-textures [texture name ...]");
+    println!("  builds the given wall textures from their patches and writes them to");
+    println!(
+        "  [texture name].png; if no textures are given, every texture is extracted. Textures"
+    );
+    println!("  that use patches which can't be found are listed.");
+    println!("- extract-sprites [sprite name ...]");
+    println!(
+        "  writes a sheet of every frame and rotation of the given sprites to [sprite name].png;"
+    );
+    println!(
+        "  if no sprites are given, every sprite is extracted. Things in the maps whose sprites"
+    );
+    println!("  are missing are listed.");
+    println!("- extract-sounds [-nopcspeaker] [lump name ...]");
+    println!(
+        "  writes the given sound effects to [lump name].wav; if no lumps are given, every DS*"
+    );
+    println!(
+        "  (DMX) and DP* (PC speaker) lump is extracted. PC speaker sounds are synthesized as"
+    );
+    println!("  square waves; -nopcspeaker leaves them out.");
+    println!("- extract-music [lump name ...]");
+    println!(
+        "  converts the given MUS music lumps to MIDI and writes them to [lump name].mid; if no"
+    );
+    println!(
+        "  lumps are given, every D_* lump is extracted. MIDI music is written out unchanged."
+    );
+    println!("- extract-genmidi");
+    println!(
+        "  writes the OPL instrument bank in GENMIDI to GENMIDI.op2 and GENMIDI.json, and each"
+    );
+    println!("  instrument's first voice to GENMIDI/[number].sbi");
+    println!("- demo [lump name or filename ...]");
+    println!(
+        "  summarizes the given demos (DEMO* lumps or .lmp files): the map, skill, length and"
+    );
+    println!("  players. If none are given, every DEMO* lump is summarized.");
+    println!("- demo-tics [lump name or filename]");
+    println!("  prints every player's input for each tic of the given demo");
+    println!("- extract-endoom");
+    println!("  writes the ENDOOM exit screen to ENDOOM.txt, ENDOOM.ans (with ANSI colors) and");
+    println!("  ENDOOM.html");
+    println!(
+        "\nPWADs given with -file are loaded on top of the main WAD, as they would be in Doom;"
+    );
+    println!(
+        "their maps and lumps replace those of the same name. The following commands edit the"
+    );
+    println!("main WAD in place, and can't be combined with -file:");
+    println!("- add [lump name] [filename] [namespace]");
+    println!(
+        "  adds the contents of the given file to the end of the WAD as a new lump. If a namespace"
+    );
+    println!(
+        "  is given (e.g. F for flats), the lump is added just before that namespace's end marker."
+    );
+    println!("- replace [lump name] [filename]");
+    println!("  replaces the contents of the given lump with the contents of the given file.");
+    println!("- rm [lump name]");
+    println!("  removes the given lump from the WAD.");
+    println!("- rename [lump name] [new name]");
+    println!("  renames the given lump.");
 
     exit(255);
 }
@@ -1,20 +1,53 @@
 // This implementation is all based on the documentary on ZDoom's wiki:
 // https://zdoom.org/wiki/WAD
 
+use std::cell::{OnceCell, RefCell};
 use std::collections::HashMap;
+use std::fmt;
 use std::fs::File;
-use std::io::{Read, Seek};
+use std::io::{BufReader, Cursor, Read, Seek, SeekFrom};
+use std::string::FromUtf8Error;
 
 use fixedstr::fstr;
 
+mod demo;
+mod edit;
+mod flats;
+mod genmidi;
+mod music;
+mod polygons;
+mod sounds;
+mod sprites;
+mod stack;
+mod text_screen;
+mod textures;
+pub mod udmf;
+mod writer;
+
+pub use demo::{Demo, TicCmd};
+pub use edit::validate_lump_name;
+pub use flats::{Flat, FlatAnimation};
+pub use genmidi::{GenMidi, OplInstrument, OplOperator, OplVoice};
+pub use music::{music_to_midi, Mus, MusEvent, MusEventKind};
+pub use polygons::{SectorDiagnostic, SectorPolygon, SectorPolygons};
+pub use sounds::{PcSpeakerSound, PcSpeakerTone, Sound};
+pub use sprites::{MissingSprite, Sprite, SpriteFrame, SpriteRotation, SpriteSet};
+pub use stack::WadStack;
+pub use text_screen::{cp437_to_char, TextCell, TextScreen};
+pub use textures::{ComposedTexture, TextureDef, TexturePatch, TextureSet};
+use udmf::UdmfMap;
+
 #[derive(Clone, Debug)]
 pub struct DirectoryEntry {
     pub name: String,
     pub offset: i32,
     pub size: i32,
+    // Lumps that have been added or replaced since the WAD was read keep their data in memory, in
+    // which case `offset` is meaningless
+    pub data: Option<Vec<u8>>,
 }
 
-#[derive(Clone, Debug)]
+#[derive(Clone, Debug, PartialEq)]
 pub struct LineDef {
     pub vertex_begin: i16,
     pub vertex_end: i16,
@@ -25,16 +58,296 @@ pub struct LineDef {
     pub sidedef_left: i16,
 }
 
+// Hexen-format linedefs replace the Doom sector tag with a one-byte special and five arguments
+#[derive(Clone, Debug)]
+pub struct HexenLineDef {
+    pub vertex_begin: i16,
+    pub vertex_end: i16,
+    pub flags: i16,
+    pub special: u8,
+    pub args: [u8; 5],
+    pub sidedef_right: i16,
+    pub sidedef_left: i16,
+}
+
+impl HexenLineDef {
+    // Hexen specials don't have a sector tag; which of their args (if any) refers to a tag depends
+    // on the special
+    fn to_linedef(&self) -> LineDef {
+        LineDef {
+            vertex_begin: self.vertex_begin,
+            vertex_end: self.vertex_end,
+            flags: self.flags,
+            line_type: self.special as i16,
+            sector_tag: 0,
+            sidedef_right: self.sidedef_right,
+            sidedef_left: self.sidedef_left,
+        }
+    }
+}
+
+// The BSP lumps are built by a node builder from the rest of the map; see
+// https://doomwiki.org/wiki/Node for details on how they fit together
+
+#[derive(Clone, Copy, Debug)]
+pub struct BoundingBox {
+    pub top: i16,
+    pub bottom: i16,
+    pub left: i16,
+    pub right: i16,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum NodeChild {
+    Node(u16),
+    SubSector(u16),
+}
+
+#[derive(Clone, Debug)]
+pub struct Node {
+    // The partition line runs from (x, y) to (x + dx, y + dy)
+    pub x: i16,
+    pub y: i16,
+    pub dx: i16,
+    pub dy: i16,
+    pub bbox_right: BoundingBox,
+    pub bbox_left: BoundingBox,
+    pub child_right: NodeChild,
+    pub child_left: NodeChild,
+}
+
+impl Node {
+    // Which side of the partition line the point is on, using the same logic as Doom's
+    // R_PointOnSide
+    pub fn child_for_point(&self, x: i16, y: i16) -> NodeChild {
+        let on_left = if self.dx == 0 {
+            if x <= self.x {
+                self.dy > 0
+            } else {
+                self.dy < 0
+            }
+        } else if self.dy == 0 {
+            if y <= self.y {
+                self.dx < 0
+            } else {
+                self.dx > 0
+            }
+        } else {
+            let left = self.dy as i64 * (x as i64 - self.x as i64);
+            let right = (y as i64 - self.y as i64) * self.dx as i64;
+            right >= left
+        };
+
+        if on_left {
+            self.child_left
+        } else {
+            self.child_right
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct Seg {
+    pub vertex_begin: u16,
+    pub vertex_end: u16,
+    pub angle: i16,
+    pub linedef: u16,
+    // false if the seg runs along the linedef's right side, true for its left side
+    pub backside: bool,
+    pub offset: i16,
+}
+
+#[derive(Clone, Debug)]
+pub struct SubSector {
+    pub seg_count: u16,
+    pub first_seg: u16,
+}
+
+// The blockmap divides the map into a grid of 128x128 blocks, each with a list of the linedefs that
+// pass through it, so that collision checks don't need to consider every linedef in the map
+#[derive(Clone, Debug)]
+pub struct Blockmap {
+    pub origin_x: i16,
+    pub origin_y: i16,
+    pub columns: u16,
+    pub rows: u16,
+    // Linedef indexes for each block, row by row starting from the bottom left
+    pub blocks: Vec<Vec<u16>>,
+}
+
+impl Blockmap {
+    pub const BLOCK_SIZE: i32 = 128;
+
+    pub fn lines_in_block(&self, column: usize, row: usize) -> &[u16] {
+        if column >= self.columns as usize || row >= self.rows as usize {
+            return &[];
+        }
+        &self.blocks[row * self.columns as usize + column]
+    }
+
+    // The (column, row) of the block containing the given map coordinates
+    pub fn block_at(&self, x: i16, y: i16) -> Option<(usize, usize)> {
+        let column = (x as i32 - self.origin_x as i32).div_euclid(Blockmap::BLOCK_SIZE);
+        let row = (y as i32 - self.origin_y as i32).div_euclid(Blockmap::BLOCK_SIZE);
+        if column < 0 || row < 0 || column >= self.columns as i32 || row >= self.rows as i32 {
+            return None;
+        }
+        Some((column as usize, row as usize))
+    }
+
+    // Every linedef in a block that overlaps the square of the given radius around the point. Like
+    // the game itself, this is conservative: callers still need to check the actual distance.
+    pub fn lines_near(&self, x: i16, y: i16, radius: i16) -> Vec<u16> {
+        let block_range = |center: i16, origin: i16, count: u16| {
+            let first = (center as i32 - radius as i32 - origin as i32)
+                .div_euclid(Blockmap::BLOCK_SIZE)
+                .max(0);
+            let last = (center as i32 + radius as i32 - origin as i32)
+                .div_euclid(Blockmap::BLOCK_SIZE)
+                .min(count as i32 - 1);
+            first..=last
+        };
+
+        let mut lines = vec![];
+        for row in block_range(y, self.origin_y, self.rows) {
+            for column in block_range(x, self.origin_x, self.columns) {
+                lines.extend_from_slice(self.lines_in_block(column as usize, row as usize));
+            }
+        }
+        lines.sort_unstable();
+        lines.dedup();
+        lines
+    }
+}
+
+// REJECT is a sector x sector bit matrix that lets the game skip line of sight checks between
+// sectors that can never see each other. It's optional as far as the game is concerned, so problems
+// with it are reported as a diagnostic rather than an error.
+#[derive(Clone, Debug)]
+pub struct RejectTable {
+    pub num_sectors: usize,
+    pub bits: Vec<u8>,
+    pub diagnostic: Option<RejectDiagnostic>,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum RejectDiagnostic {
+    Missing,
+    // Valid, but it doesn't reject anything, so every sight check is done the slow way
+    ZeroFilled,
+    // Vanilla Doom reads past the end of a short REJECT lump into whatever follows it in memory
+    Short { expected: usize, actual: usize },
+}
+
+impl fmt::Display for RejectDiagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RejectDiagnostic::Missing => write!(f, "REJECT lump is missing"),
+            RejectDiagnostic::ZeroFilled => write!(f, "REJECT lump is zero-filled"),
+            RejectDiagnostic::Short { expected, actual } => write!(
+                f,
+                "REJECT lump is {} bytes, but should be {} bytes",
+                actual, expected
+            ),
+        }
+    }
+}
+
+impl RejectTable {
+    pub fn new(num_sectors: usize, bits: Vec<u8>) -> RejectTable {
+        let expected = (num_sectors * num_sectors).div_ceil(8);
+        let diagnostic = if bits.len() < expected {
+            Some(RejectDiagnostic::Short {
+                expected,
+                actual: bits.len(),
+            })
+        } else if bits.iter().all(|&byte| byte == 0) {
+            Some(RejectDiagnostic::ZeroFilled)
+        } else {
+            None
+        };
+
+        RejectTable {
+            num_sectors,
+            bits,
+            diagnostic,
+        }
+    }
+
+    pub fn missing(num_sectors: usize) -> RejectTable {
+        RejectTable {
+            num_sectors,
+            bits: vec![],
+            diagnostic: Some(RejectDiagnostic::Missing),
+        }
+    }
+
+    // Whether monsters in `sector_a` are allowed to look for targets in `sector_b`; bits that are
+    // missing from a short table are treated as unset, so the sight check goes ahead
+    pub fn can_see(&self, sector_a: usize, sector_b: usize) -> bool {
+        if sector_a >= self.num_sectors || sector_b >= self.num_sectors {
+            return true;
+        }
+        let bit = sector_a * self.num_sectors + sector_b;
+        match self.bits.get(bit / 8) {
+            Some(byte) => byte & (1 << (bit % 8)) == 0,
+            None => true,
+        }
+    }
+}
+
 pub struct MapData {
     pub name: String,
+    pub format: MapFormat,
+    // These are always populated, regardless of the map's format, so geometry can be handled the
+    // same way for every map; format-specific data lives in `format`
     pub linedefs: Vec<LineDef>,
     pub sectors: Vec<Sector>,
     pub sidedefs: Vec<SideDef>,
     pub things: Vec<Thing>,
     pub vertexes: Vec<Vertex>,
+    // These are left empty if the map hasn't been run through a node builder, or if its nodes are
+    // in a format other than vanilla Doom's
+    pub nodes: Vec<Node>,
+    pub segs: Vec<Seg>,
+    pub subsectors: Vec<SubSector>,
+    // Missing (or empty, when a node builder still needs to be run) in many PWADs
+    pub blockmap: Option<Blockmap>,
+    pub reject: RejectTable,
 }
 
-#[derive(Clone, Debug)]
+impl MapData {
+    // Walks the BSP tree from the root node (which is always the last one) to find the subsector
+    // that contains the given point
+    pub fn subsector_at(&self, x: i16, y: i16) -> Option<usize> {
+        if self.nodes.is_empty() {
+            // A map with a single subsector doesn't need any nodes
+            return Some(0).filter(|_| self.subsectors.len() == 1);
+        }
+
+        let mut child = NodeChild::Node(self.nodes.len() as u16 - 1);
+        // A well-formed tree can't be deeper than it has nodes, so this guards against cycles
+        for _ in 0..=self.nodes.len() {
+            match child {
+                NodeChild::Node(index) => {
+                    child = self.nodes.get(index as usize)?.child_for_point(x, y)
+                }
+                NodeChild::SubSector(index) => {
+                    return Some(index as usize).filter(|&index| index < self.subsectors.len())
+                }
+            }
+        }
+        None
+    }
+
+    pub fn subsector_segs(&self, subsector: &SubSector) -> &[Seg] {
+        let first = (subsector.first_seg as usize).min(self.segs.len());
+        let last = (first + subsector.seg_count as usize).min(self.segs.len());
+        &self.segs[first..last]
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
 pub struct Sector {
     pub floor_height: i16,
     pub ceiling_height: i16,
@@ -45,7 +358,7 @@ pub struct Sector {
     pub sector_tag: u16,
 }
 
-#[derive(Clone, Debug)]
+#[derive(Clone, Debug, PartialEq)]
 pub struct SideDef {
     pub x: i16,
     pub y: i16,
@@ -55,6 +368,7 @@ pub struct SideDef {
     pub sector: u16,
 }
 
+#[derive(Clone, Debug, PartialEq)]
 pub struct Thing {
     pub x: i16,
     pub y: i16,
@@ -63,142 +377,673 @@ pub struct Thing {
     pub spawn_flags: i16,
 }
 
+#[derive(Clone, Debug)]
+pub struct HexenThing {
+    pub tid: i16,
+    pub x: i16,
+    pub y: i16,
+    pub z: i16,
+    pub angle: i16,
+    pub thing_type: i16,
+    pub flags: i16,
+    pub special: u8,
+    pub args: [u8; 5],
+}
+
+impl HexenThing {
+    fn to_thing(&self) -> Thing {
+        Thing {
+            x: self.x,
+            y: self.y,
+            angle: self.angle,
+            thing_type: self.thing_type,
+            spawn_flags: self.flags,
+        }
+    }
+}
+
+pub enum MapFormat {
+    Doom,
+    // Hexen-format maps are identified by the presence of a BEHAVIOR lump, which holds their
+    // compiled ACS scripts
+    Hexen {
+        linedefs: Vec<HexenLineDef>,
+        things: Vec<HexenThing>,
+        behavior: Vec<u8>,
+    },
+    // UDMF maps keep everything, including fractional coordinates and port-specific properties,
+    // in their TEXTMAP lump
+    Udmf(UdmfMap),
+}
+
 #[derive(Clone, Copy, Debug, PartialEq)]
 pub struct Vertex {
     pub x: i16,
     pub y: i16,
 }
 
+// One of the 14 palettes in PLAYPAL. Palette 0 is the one normally in use; the others are tinted
+// red (taking damage), gold (picking up items) and green (radiation suit).
+#[derive(Clone, Debug)]
+pub struct Palette {
+    pub colors: [[u8; 3]; 256],
+}
+
+impl Palette {
+    pub fn rgba(&self, index: u8) -> [u8; 4] {
+        let [r, g, b] = self.colors[index as usize];
+        [r, g, b, 255]
+    }
+}
+
+// COLORMAP remaps palette indexes to darker ones. Tables 0 to 31 go from full brightness to
+// nearly black, table 32 is the invulnerability effect and table 33 is all black.
+#[derive(Clone, Debug)]
+pub struct Colormap {
+    pub tables: Vec<[u8; 256]>,
+}
+
+impl Colormap {
+    // The table Doom uses for a sector's floor or ceiling at the given light level, ignoring the
+    // extra darkening it applies with distance
+    pub fn light_table(&self, light_level: i16) -> Option<&[u8; 256]> {
+        let index = 31 - light_level.clamp(0, 255) as usize / 8;
+        self.tables.get(index)
+    }
+}
+
+// The column-based format used for almost every graphic in Doom other than flats: wall patches,
+// sprites, the status bar, menus, fonts and full-screen pictures such as TITLEPIC
+#[derive(Clone, Debug)]
+pub struct Picture {
+    pub width: u16,
+    pub height: u16,
+    // How far the picture is drawn to the left of and above its origin; for sprites, that's the
+    // point where the thing touches the floor
+    pub left_offset: i16,
+    pub top_offset: i16,
+    // Palette indexes, row by row, with None wherever the picture is transparent
+    pub pixels: Vec<Option<u8>>,
+}
+
+impl Picture {
+    pub fn decode(lump: &str, bytes: &[u8]) -> Result<Picture, WadError> {
+        // https://doomwiki.org/wiki/Picture_format
+        let invalid = |message: &str| WadError::InvalidLump {
+            lump: lump.to_string(),
+            message: message.to_string(),
+        };
+
+        if bytes.len() < 8 {
+            return Err(invalid("too short to be a picture"));
+        }
+        let width = u16::from_le_bytes([bytes[0], bytes[1]]);
+        let height = u16::from_le_bytes([bytes[2], bytes[3]]);
+        let left_offset = i16::from_le_bytes([bytes[4], bytes[5]]);
+        let top_offset = i16::from_le_bytes([bytes[6], bytes[7]]);
+        // Nothing in Doom comes close to this size; anything bigger is almost certainly not a
+        // picture at all
+        if width == 0 || height == 0 || width > 4096 || height > 4096 {
+            return Err(invalid("picture has an invalid size"));
+        }
+
+        let columns_end = 8 + width as usize * 4;
+        let column_offsets = bytes
+            .get(8..columns_end)
+            .ok_or_else(|| invalid("picture's column offsets run past the end of the lump"))?;
+
+        let mut pixels = vec![None; width as usize * height as usize];
+        for (x, offset_buf) in column_offsets.chunks_exact(4).enumerate() {
+            let mut pos = u32::from_le_bytes(offset_buf.try_into().unwrap()) as usize;
+            if pos < columns_end {
+                return Err(invalid("picture column starts inside the header"));
+            }
+
+            // Each column is a series of posts: runs of opaque pixels starting at a given row
+            let mut top: i32 = -1;
+            loop {
+                let top_delta = *bytes
+                    .get(pos)
+                    .ok_or_else(|| invalid("picture column runs past the end of the lump"))?;
+                if top_delta == 0xFF {
+                    break;
+                }
+                // In "tall" patches, a post that doesn't start below the previous one is relative
+                // to it instead, which allows columns taller than 254 pixels
+                if top_delta as i32 <= top {
+                    top += top_delta as i32;
+                } else {
+                    top = top_delta as i32;
+                }
+
+                // The post's length is followed by its pixels, with an unused byte either side
+                let length = *bytes
+                    .get(pos + 1)
+                    .ok_or_else(|| invalid("picture column runs past the end of the lump"))?
+                    as usize;
+                let post = bytes
+                    .get(pos + 3..pos + 3 + length)
+                    .ok_or_else(|| invalid("picture column runs past the end of the lump"))?;
+                // Like the game, pixels that fall outside the picture are ignored
+                for (y, &index) in (top as usize..height as usize).zip(post) {
+                    pixels[y * width as usize + x] = Some(index);
+                }
+                pos += length + 4;
+            }
+        }
+
+        Ok(Picture {
+            width,
+            height,
+            left_offset,
+            top_offset,
+            pixels,
+        })
+    }
+
+    // 4 bytes per pixel, row by row; transparent pixels are transparent black
+    pub fn to_rgba(&self, palette: &Palette) -> Vec<u8> {
+        self.pixels
+            .iter()
+            .flat_map(|pixel| pixel.map_or([0; 4], |index| palette.rgba(index)))
+            .collect()
+    }
+}
+
 pub struct Wad {
     pub directory: Vec<DirectoryEntry>,
-    pub maps: Vec<MapData>,
     pub wad_type: WadType,
+    // Maps are decoded the first time they're asked for, so that just listing the directory of a
+    // large WAD doesn't read every map in it
+    decoded_maps: OnceCell<DecodedMaps>,
+    // Lump data is read from here on demand, rather than being loaded up front
+    source: RefCell<Box<dyn ReadSeek>>,
+}
+
+trait ReadSeek: Read + Seek {}
+impl<T: Read + Seek> ReadSeek for T {}
+
+// A lump in a Wad's directory, whose data is only read when asked for
+pub struct Lump<'a> {
+    wad: &'a Wad,
+    pub index: usize,
+    pub entry: &'a DirectoryEntry,
+}
+
+impl Lump<'_> {
+    pub fn name(&self) -> &str {
+        &self.entry.name
+    }
+
+    pub fn bytes(&self) -> Result<Vec<u8>, WadError> {
+        self.wad.lump_bytes(self.entry)
+    }
 }
 
 impl Wad {
-    pub fn open(filename: &str) -> Result<Wad, String> {
-        let mut file = File::open(filename).map_err(|err| err.to_string())?;
+    pub fn open(filename: &str) -> Result<Wad, WadError> {
+        let file = File::open(filename)?;
+
+        Wad::from_reader(BufReader::new(file))
+    }
+
+    // The bytes are copied, since lumps are read from them lazily for the lifetime of the Wad
+    pub fn from_bytes(bytes: &[u8]) -> Result<Wad, WadError> {
+        Wad::from_reader(Cursor::new(bytes.to_vec()))
+    }
 
-        let (wad_type, directory_offset, num_directory_entries) = decode_header(&mut file)?;
-        let directory = decode_directory(&mut file, directory_offset, num_directory_entries)?;
-        let maps = decode_maps(&mut file, &directory)?;
+    pub fn from_reader<R: Read + Seek + 'static>(mut reader: R) -> Result<Wad, WadError> {
+        let file_size = reader.seek(SeekFrom::End(0))?;
+        reader.seek(SeekFrom::Start(0))?;
+
+        let (wad_type, directory_offset, num_directory_entries) = decode_header(&mut reader)?;
+        let directory = decode_directory(
+            &mut reader,
+            directory_offset,
+            num_directory_entries,
+            file_size,
+        )?;
 
         Ok(Wad {
             directory,
-            maps,
             wad_type,
+            decoded_maps: OnceCell::new(),
+            source: RefCell::new(Box::new(reader)),
         })
     }
+
+    pub fn maps(&self) -> &[MapData] {
+        &self.decode_maps().maps
+    }
+
+    // Maps can be edited but not added or removed this way, since each one is saved back over the
+    // lumps it was decoded from
+    pub fn maps_mut(&mut self) -> &mut [MapData] {
+        self.decode_maps();
+        &mut self.decoded_maps.get_mut().unwrap().maps
+    }
+
+    // Why each map that couldn't be decoded failed; a broken map doesn't stop the rest of the WAD
+    // loading
+    pub fn map_errors(&self) -> &[WadError] {
+        &self.decode_maps().errors
+    }
+
+    fn decode_maps(&self) -> &DecodedMaps {
+        self.decoded_maps
+            .get_or_init(|| decode_maps(&mut *self.source.borrow_mut(), &self.directory))
+    }
+
+    // The maps, but only if something has already caused them to be decoded
+    fn decoded_maps(&self) -> Option<&DecodedMaps> {
+        self.decoded_maps.get()
+    }
+
+    pub fn lump_bytes(&self, entry: &DirectoryEntry) -> Result<Vec<u8>, WadError> {
+        read_lump(&mut *self.source.borrow_mut(), entry)
+    }
+
+    // Like Doom itself, if there are multiple lumps with the same name, the last one wins
+    pub fn find_lump(&self, name: &str) -> Option<&DirectoryEntry> {
+        self.directory
+            .iter()
+            .rev()
+            .find(|entry| entry.name.eq_ignore_ascii_case(name))
+    }
+
+    pub fn find_lump_index(&self, name: &str) -> Option<usize> {
+        self.directory
+            .iter()
+            .rposition(|entry| entry.name.eq_ignore_ascii_case(name))
+    }
+
+    // Finds the first lump with the given name at or after `start_index` in the directory; useful
+    // for finding lumps that belong to a particular map or namespace
+    pub fn find_lump_after(
+        &self,
+        name: &str,
+        start_index: usize,
+    ) -> Option<(usize, &DirectoryEntry)> {
+        self.directory
+            .iter()
+            .enumerate()
+            .skip(start_index)
+            .find(|(_, entry)| entry.name.eq_ignore_ascii_case(name))
+    }
+
+    // The lumps between a namespace's start and end markers, such as F_START and F_END for flats
+    // (namespace "F"). The doubled markers used by PWADs (FF_START/FF_END) count too, and a
+    // namespace can appear more than once; nested markers such as F1_START are skipped.
+    pub fn namespace_lumps(&self, namespace: &str) -> Vec<Lump<'_>> {
+        let is_marker = |name: &str, suffix: &str| {
+            name.eq_ignore_ascii_case(&format!("{}{}", namespace, suffix))
+                || name.eq_ignore_ascii_case(&format!("{}{}{}", namespace, namespace, suffix))
+        };
+
+        let mut in_namespace = false;
+        let mut lumps = vec![];
+        for lump in self.lumps() {
+            if is_marker(lump.name(), "_START") {
+                in_namespace = true;
+            } else if is_marker(lump.name(), "_END") {
+                in_namespace = false;
+            } else if in_namespace && lump.entry.size > 0 {
+                lumps.push(lump);
+            }
+        }
+        lumps
+    }
+
+    pub fn palettes(&self) -> Result<Vec<Palette>, WadError> {
+        let entry = self.find_lump("PLAYPAL").ok_or(WadError::MissingLump {
+            lump: "PLAYPAL".to_string(),
+        })?;
+        decode_playpal(entry, &self.lump_bytes(entry)?)
+    }
+
+    pub fn colormap(&self) -> Result<Colormap, WadError> {
+        let entry = self.find_lump("COLORMAP").ok_or(WadError::MissingLump {
+            lump: "COLORMAP".to_string(),
+        })?;
+        decode_colormap(entry, &self.lump_bytes(entry)?)
+    }
+
+    pub fn lumps(&self) -> impl Iterator<Item = Lump<'_>> {
+        self.directory
+            .iter()
+            .enumerate()
+            .map(move |(index, entry)| Lump {
+                wad: self,
+                index,
+                entry,
+            })
+    }
 }
 
+#[allow(clippy::upper_case_acronyms)]
 #[derive(Debug)]
 pub enum WadType {
     IWAD,
     PWAD,
 }
 
+#[derive(Debug)]
+pub enum WadError {
+    Io(std::io::Error),
+    // An I/O error while reading a particular lump
+    LumpIo {
+        lump: String,
+        source: std::io::Error,
+    },
+    BadSignature([u8; 4]),
+    DirectoryOutOfBounds {
+        offset: i32,
+        num_entries: i32,
+        file_size: u64,
+    },
+    // A lump with an invalid name, or, if there's an index, a name stored in one of the lump's
+    // records that isn't valid
+    InvalidName {
+        lump: String,
+        index: Option<usize>,
+    },
+    InvalidTextMap {
+        line: usize,
+        message: String,
+    },
+    // A lump whose contents don't match the format it's supposed to be in
+    InvalidLump {
+        lump: String,
+        message: String,
+    },
+    LumpIndexOutOfBounds {
+        index: usize,
+        num_lumps: usize,
+    },
+    LumpSizeNotMultiple {
+        lump: String,
+        size: i32,
+        record_size: usize,
+    },
+    MissingLump {
+        lump: String,
+    },
+    TruncatedLump {
+        lump: String,
+        offset: i32,
+        size: i32,
+    },
+    // Changes to a map that `Wad::save` can't write out without losing some of them
+    UnsavableMapEdits {
+        map: String,
+        message: String,
+    },
+    // Wraps an error that happened while decoding one of the lumps belonging to a map
+    InMap {
+        map: String,
+        source: Box<WadError>,
+    },
+}
+
+impl WadError {
+    fn in_map(self, map: &str) -> WadError {
+        WadError::InMap {
+            map: map.to_string(),
+            source: Box::new(self),
+        }
+    }
+}
+
+impl fmt::Display for WadError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            WadError::Io(err) => write!(f, "{}", err),
+            WadError::LumpIo { lump, source } => write!(f, "{}: {}", lump, source),
+            WadError::BadSignature(signature) => write!(
+                f,
+                "Invalid WAD; expected signature {:?} to be {:?} ('IWAD') or {:?} ('PWAD')",
+                signature,
+                "IWAD".as_bytes(),
+                "PWAD".as_bytes()
+            ),
+            WadError::DirectoryOutOfBounds {
+                offset,
+                num_entries,
+                file_size,
+            } => write!(
+                f,
+                "Directory of {} entries at offset {} does not fit in a {} byte file",
+                num_entries, offset, file_size
+            ),
+            WadError::InvalidName {
+                lump,
+                index: Some(index),
+            } => write!(f, "Record {} of {} has an invalid name", index, lump),
+            WadError::InvalidName { lump, index: None } => write!(
+                f,
+                "{:?} is not a valid lump name; names are 1 to 8 characters from A-Z, 0-9 and []-_\\^",
+                lump
+            ),
+            WadError::InvalidTextMap { line, message } => {
+                write!(f, "TEXTMAP line {}: {}", line, message)
+            }
+            WadError::InvalidLump { lump, message } => write!(f, "{}: {}", lump, message),
+            WadError::LumpIndexOutOfBounds { index, num_lumps } => write!(
+                f,
+                "There is no lump {}; the directory has {} lumps",
+                index, num_lumps
+            ),
+            WadError::LumpSizeNotMultiple {
+                lump,
+                size,
+                record_size,
+            } => write!(
+                f,
+                "{} is {} bytes, which is not a multiple of its {} byte record size",
+                lump, size, record_size
+            ),
+            WadError::MissingLump { lump } => write!(f, "No {} lump found", lump),
+            WadError::TruncatedLump { lump, offset, size } => write!(
+                f,
+                "{} claims to be {} bytes starting at {}, but the file ends before that",
+                lump, size, offset
+            ),
+            WadError::UnsavableMapEdits { map, message } => {
+                write!(f, "Can't save the changes to {}: {}", map, message)
+            }
+            WadError::InMap { map, source } => write!(f, "{}: {}", map, source),
+        }
+    }
+}
+
+impl std::error::Error for WadError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            WadError::Io(err) => Some(err),
+            WadError::LumpIo { source, .. } => Some(source),
+            WadError::InMap { source, .. } => Some(source.as_ref()),
+            _ => None,
+        }
+    }
+}
+
+impl From<std::io::Error> for WadError {
+    fn from(err: std::io::Error) -> WadError {
+        WadError::Io(err)
+    }
+}
+
 //
 
-fn decode_header(file: &mut File) -> Result<(WadType, i32, i32), String> {
+fn decode_header<R: Read + Seek>(file: &mut R) -> Result<(WadType, i32, i32), WadError> {
     // https://zdoom.org/wiki/WAD#Header
 
     let mut header_buf = [0; 12];
-    file.read_exact(&mut header_buf)
-        .map_err(|err| err.to_string())?;
-
-    let v = header_buf[0..4].to_vec();
-    let wad_type = match String::from_utf8(v) {
-        Ok(str) if str.eq("IWAD") => WadType::IWAD,
-        Ok(str) if str.eq("PWAD") => WadType::PWAD,
-        _ => {
-            return Err(format!(
-                "Invalid WAD; expected signature {:?} to be {:?} ('IWAD') or ({:?}) ('PWAD')",
-                header_buf[0..4].to_vec(),
-                String::from("IWAD").as_bytes(),
-                String::from("PWAD").as_bytes()
-            ))
-        }
+    file.read_exact(&mut header_buf)?;
+
+    let signature: [u8; 4] = header_buf[0..4].try_into().unwrap();
+    let wad_type = match &signature {
+        b"IWAD" => WadType::IWAD,
+        b"PWAD" => WadType::PWAD,
+        _ => return Err(WadError::BadSignature(signature)),
     };
 
-    let num_directory_entries = i32::from_le_bytes(
-        header_buf[4..8]
-            .try_into()
-            .map_err(|_| "Failed to get num_directory_entries bytes from buffer")?,
-    );
-    let directory_offset = i32::from_le_bytes(
-        header_buf[8..12]
-            .try_into()
-            .map_err(|_| "Failed to get directory_offset bytes from buffer")?,
-    );
+    let num_directory_entries = i32::from_le_bytes(header_buf[4..8].try_into().unwrap());
+    let directory_offset = i32::from_le_bytes(header_buf[8..12].try_into().unwrap());
 
     Ok((wad_type, directory_offset, num_directory_entries))
 }
 
-fn decode_directory(
-    file: &mut File,
+fn decode_directory<R: Read + Seek>(
+    file: &mut R,
     offset: i32,
     num_entries: i32,
-) -> Result<Vec<DirectoryEntry>, String> {
+    file_size: u64,
+) -> Result<Vec<DirectoryEntry>, WadError> {
     // https://zdoom.org/wiki/WAD#Directory
     // The directory associates names of lumps with the data that belong to them. It
     // consists of a number of entries, each with a length of 16 bytes. The length of the
     // directory is determined by the number given in the WAD header.
 
+    let directory_end = offset as i64 + num_entries as i64 * 16;
+    if offset < 0 || num_entries < 0 || directory_end as u64 > file_size {
+        return Err(WadError::DirectoryOutOfBounds {
+            offset,
+            num_entries,
+            file_size,
+        });
+    }
+
     let mut entries: Vec<DirectoryEntry> = Vec::new();
     let mut entry_buf = [0; 16];
-    for i in 0..num_entries {
-        let entry_offset = offset + (i * 16);
-        file.seek(std::io::SeekFrom::Start(entry_offset as u64))
-            .map_err(|err| err.to_string())?;
-        file.read_exact(&mut entry_buf)
-            .map_err(|err| err.to_string())?;
+    file.seek(SeekFrom::Start(offset as u64))?;
+    for _ in 0..(num_entries as usize) {
+        file.read_exact(&mut entry_buf)?;
 
         let lump_offset = entry_buf[0..4].try_into().map(i32::from_le_bytes).unwrap();
         let lump_size = entry_buf[4..8].try_into().map(i32::from_le_bytes).unwrap();
-        let lump_name = buf_to_string(&entry_buf[8..16])
-            .map_err(|_| format!("Lump {} at offset {} has invalid name", &i, &entry_offset))?;
+        let lump_name = buf_to_string(&entry_buf[8..16]).map_err(|_| WadError::InvalidName {
+            lump: String::from_utf8_lossy(&entry_buf[8..16]).into_owned(),
+            index: None,
+        })?;
 
         entries.push(DirectoryEntry {
             name: lump_name,
             offset: lump_offset,
             size: lump_size,
+            data: None,
         })
     }
 
     Ok(entries)
 }
 
-fn buf_to_fstr<const N: usize>(input_buf: &[u8]) -> Result<fstr<N>, String> {
-    match buf_to_string(input_buf) {
-        Ok(str) => Ok(fstr::from(str)),
-        Err(err) => Err(err.to_string()),
-    }
+fn buf_to_fstr<const N: usize>(input_buf: &[u8]) -> Result<fstr<N>, FromUtf8Error> {
+    buf_to_string(input_buf).map(fstr::from)
 }
 
-fn buf_to_string(input_buf: &[u8]) -> Result<String, String> {
+fn buf_to_string(input_buf: &[u8]) -> Result<String, FromUtf8Error> {
     // Strings in WADs are fixed length, end-padded with null characters as needed.
     let end_pos = input_buf
         .iter()
         .position(|c| *c == 0)
         .unwrap_or(input_buf.len());
 
-    match String::from_utf8(input_buf[0..end_pos].to_vec()) {
-        Ok(str) => Ok(str.trim_end_matches(char::is_control).to_owned()),
-        Err(err) => Err(err.to_string()),
+    String::from_utf8(input_buf[0..end_pos].to_vec())
+        .map(|str| str.trim_end_matches(char::is_control).to_owned())
+}
+
+// Seeks to the start of the given lump and returns the number of `record_size` byte records in it.
+// The lump has to fit in the file, so that a bogus size never gets as far as allocating a buffer.
+fn seek_to_records<R: Read + Seek>(
+    file: &mut R,
+    entry: &DirectoryEntry,
+    record_size: usize,
+) -> Result<usize, WadError> {
+    if entry.size < 0 || !(entry.size as usize).is_multiple_of(record_size) {
+        return Err(WadError::LumpSizeNotMultiple {
+            lump: entry.name.clone(),
+            size: entry.size,
+            record_size,
+        });
     }
+
+    // Empty lumps are never read, so their offset can be anything
+    if entry.size == 0 {
+        return Ok(0);
+    }
+
+    let file_size = file
+        .seek(SeekFrom::End(0))
+        .map_err(|err| lump_io_error(entry, err))?;
+    let lump_end = entry.offset as i64 + entry.size as i64;
+    if entry.offset < 0 || lump_end as u64 > file_size {
+        return Err(WadError::TruncatedLump {
+            lump: entry.name.clone(),
+            offset: entry.offset,
+            size: entry.size,
+        });
+    }
+    file.seek(SeekFrom::Start(entry.offset as u64))
+        .map_err(|err| lump_io_error(entry, err))?;
+
+    Ok(entry.size as usize / record_size)
 }
 
-fn decode_linedefs(file: &mut File, entry: &DirectoryEntry) -> Result<Vec<LineDef>, String> {
-    const LINEDEF_SIZE: usize = std::mem::size_of::<LineDef>();
-    assert!(entry.size % LINEDEF_SIZE as i32 == 0);
+fn read_record<R: Read>(
+    file: &mut R,
+    entry: &DirectoryEntry,
+    buf: &mut [u8],
+) -> Result<(), WadError> {
+    file.read_exact(buf).map_err(|err| match err.kind() {
+        std::io::ErrorKind::UnexpectedEof => WadError::TruncatedLump {
+            lump: entry.name.clone(),
+            offset: entry.offset,
+            size: entry.size,
+        },
+        _ => lump_io_error(entry, err),
+    })
+}
 
-    file.seek(std::io::SeekFrom::Start(entry.offset as u64))
-        .map_err(|err| err.to_string())?;
+fn lump_io_error(entry: &DirectoryEntry, err: std::io::Error) -> WadError {
+    WadError::LumpIo {
+        lump: entry.name.clone(),
+        source: err,
+    }
+}
+
+// Lumps that have been replaced in memory are read from there rather than from the source
+fn read_lump<R: Read + Seek>(file: &mut R, entry: &DirectoryEntry) -> Result<Vec<u8>, WadError> {
+    if let Some(data) = &entry.data {
+        return Ok(data.clone());
+    }
+
+    let size = seek_to_records(file, entry, 1)?;
+
+    let mut buf = vec![0; size];
+    read_record(file, entry, &mut buf)?;
+
+    Ok(buf)
+}
+
+fn invalid_name(entry: &DirectoryEntry, index: usize) -> WadError {
+    WadError::InvalidName {
+        lump: entry.name.clone(),
+        index: Some(index),
+    }
+}
+
+fn decode_linedefs<R: Read + Seek>(
+    file: &mut R,
+    entry: &DirectoryEntry,
+) -> Result<Vec<LineDef>, WadError> {
+    const LINEDEF_SIZE: usize = 14;
+    let num_records = seek_to_records(file, entry, LINEDEF_SIZE)?;
 
     let mut buf = [0; LINEDEF_SIZE];
     let mut linedefs = vec![];
-    for _ in 0..(entry.size / LINEDEF_SIZE as i32) {
-        file.read_exact(&mut buf).map_err(|err| err.to_string())?;
+    for _ in 0..num_records {
+        read_record(file, entry, &mut buf)?;
 
         let ints: Vec<i16> = buf
             .chunks_exact(2)
@@ -219,24 +1064,183 @@ fn decode_linedefs(file: &mut File, entry: &DirectoryEntry) -> Result<Vec<LineDe
     Ok(linedefs)
 }
 
-fn decode_sectors(file: &mut File, entry: &DirectoryEntry) -> Result<Vec<Sector>, String> {
-    const SECTOR_SIZE: usize = 26; // can't use std::mem::size_of::<Sector>() because it has fstr's rather than 8-byte character arrays, as in the WAD
-    assert!(entry.size % SECTOR_SIZE as i32 == 0);
+fn decode_hexen_linedefs<R: Read + Seek>(
+    file: &mut R,
+    entry: &DirectoryEntry,
+) -> Result<Vec<HexenLineDef>, WadError> {
+    const HEXEN_LINEDEF_SIZE: usize = 16;
+    let num_records = seek_to_records(file, entry, HEXEN_LINEDEF_SIZE)?;
 
-    let _ = file.seek(std::io::SeekFrom::Start(entry.offset as u64));
+    let mut buf = [0; HEXEN_LINEDEF_SIZE];
+    let mut linedefs = vec![];
+    for _ in 0..num_records {
+        read_record(file, entry, &mut buf)?;
+
+        linedefs.push(HexenLineDef {
+            vertex_begin: i16::from_le_bytes(buf[0..2].try_into().unwrap()),
+            vertex_end: i16::from_le_bytes(buf[2..4].try_into().unwrap()),
+            flags: i16::from_le_bytes(buf[4..6].try_into().unwrap()),
+            special: buf[6],
+            args: buf[7..12].try_into().unwrap(),
+            sidedef_right: i16::from_le_bytes(buf[12..14].try_into().unwrap()),
+            sidedef_left: i16::from_le_bytes(buf[14..16].try_into().unwrap()),
+        })
+    }
+
+    Ok(linedefs)
+}
+
+fn decode_nodes<R: Read + Seek>(
+    file: &mut R,
+    entry: &DirectoryEntry,
+) -> Result<Vec<Node>, WadError> {
+    const NODE_SIZE: usize = 28;
+    let num_records = seek_to_records(file, entry, NODE_SIZE)?;
+
+    // The high bit of a child index says whether it refers to a subsector or another node
+    const SUBSECTOR_BIT: u16 = 0x8000;
+    let child = |index: u16| {
+        if index & SUBSECTOR_BIT != 0 {
+            NodeChild::SubSector(index & !SUBSECTOR_BIT)
+        } else {
+            NodeChild::Node(index)
+        }
+    };
+
+    let mut buf = [0; NODE_SIZE];
+    let mut nodes = vec![];
+    for _ in 0..num_records {
+        read_record(file, entry, &mut buf)?;
+
+        let ints: Vec<i16> = buf
+            .chunks_exact(2)
+            .map(|c| c.try_into().map(i16::from_le_bytes).unwrap())
+            .collect();
+
+        nodes.push(Node {
+            x: ints[0],
+            y: ints[1],
+            dx: ints[2],
+            dy: ints[3],
+            bbox_right: BoundingBox {
+                top: ints[4],
+                bottom: ints[5],
+                left: ints[6],
+                right: ints[7],
+            },
+            bbox_left: BoundingBox {
+                top: ints[8],
+                bottom: ints[9],
+                left: ints[10],
+                right: ints[11],
+            },
+            child_right: child(ints[12] as u16),
+            child_left: child(ints[13] as u16),
+        })
+    }
+    Ok(nodes)
+}
+
+fn decode_segs<R: Read + Seek>(file: &mut R, entry: &DirectoryEntry) -> Result<Vec<Seg>, WadError> {
+    const SEG_SIZE: usize = 12;
+    let num_records = seek_to_records(file, entry, SEG_SIZE)?;
+
+    let mut buf = [0; SEG_SIZE];
+    let mut segs = vec![];
+    for _ in 0..num_records {
+        read_record(file, entry, &mut buf)?;
+
+        segs.push(Seg {
+            vertex_begin: u16::from_le_bytes(buf[0..2].try_into().unwrap()),
+            vertex_end: u16::from_le_bytes(buf[2..4].try_into().unwrap()),
+            angle: i16::from_le_bytes(buf[4..6].try_into().unwrap()),
+            linedef: u16::from_le_bytes(buf[6..8].try_into().unwrap()),
+            backside: i16::from_le_bytes(buf[8..10].try_into().unwrap()) != 0,
+            offset: i16::from_le_bytes(buf[10..12].try_into().unwrap()),
+        })
+    }
+    Ok(segs)
+}
+
+fn decode_subsectors<R: Read + Seek>(
+    file: &mut R,
+    entry: &DirectoryEntry,
+) -> Result<Vec<SubSector>, WadError> {
+    const SUBSECTOR_SIZE: usize = 4;
+    let num_records = seek_to_records(file, entry, SUBSECTOR_SIZE)?;
+
+    let mut buf = [0; SUBSECTOR_SIZE];
+    let mut subsectors = vec![];
+    for _ in 0..num_records {
+        read_record(file, entry, &mut buf)?;
+
+        subsectors.push(SubSector {
+            seg_count: u16::from_le_bytes(buf[0..2].try_into().unwrap()),
+            first_seg: u16::from_le_bytes(buf[2..4].try_into().unwrap()),
+        })
+    }
+    Ok(subsectors)
+}
+
+fn decode_blockmap(entry: &DirectoryEntry, bytes: &[u8]) -> Result<Blockmap, WadError> {
+    // Everything in the blockmap is a 16-bit word, and offsets into it are counted in words
+    let words: Vec<u16> = split_records(&entry.name, bytes, 2)?
+        .map(|c| c.try_into().map(u16::from_le_bytes).unwrap())
+        .collect();
+
+    let truncated = || WadError::TruncatedLump {
+        lump: entry.name.clone(),
+        offset: entry.offset,
+        size: entry.size,
+    };
+    let header = words.get(0..4).ok_or_else(truncated)?;
+    let (columns, rows) = (header[2], header[3]);
+    let num_blocks = columns as usize * rows as usize;
+    let offsets = words.get(4..4 + num_blocks).ok_or_else(truncated)?;
+
+    // Each block list starts with a 0 and is terminated by 0xFFFF. Lists that point outside the
+    // lump or that run off the end of it are tolerated, since the game never reads past the lump.
+    const BLOCKLIST_START: u16 = 0x0000;
+    const BLOCKLIST_END: u16 = 0xFFFF;
+    let blocks = offsets
+        .iter()
+        .map(|&offset| {
+            let list = words.get(offset as usize..).unwrap_or_default();
+            let list = list.strip_prefix(&[BLOCKLIST_START]).unwrap_or(list);
+            list.iter()
+                .take_while(|&&word| word != BLOCKLIST_END)
+                .copied()
+                .collect()
+        })
+        .collect();
+
+    Ok(Blockmap {
+        origin_x: header[0] as i16,
+        origin_y: header[1] as i16,
+        columns,
+        rows,
+        blocks,
+    })
+}
+
+fn decode_sectors<R: Read + Seek>(
+    file: &mut R,
+    entry: &DirectoryEntry,
+) -> Result<Vec<Sector>, WadError> {
+    const SECTOR_SIZE: usize = 26; // can't use std::mem::size_of::<Sector>() because it has fstr's rather than 8-byte character arrays, as in the WAD
+    let num_records = seek_to_records(file, entry, SECTOR_SIZE)?;
 
     let mut buf = [0; SECTOR_SIZE];
     let mut sectors = vec![];
-    for sector_id in 0..(entry.size / SECTOR_SIZE as i32) {
-        file.read_exact(&mut buf).map_err(|err| err.to_string())?;
+    for sector_id in 0..num_records {
+        read_record(file, entry, &mut buf)?;
 
         sectors.push(Sector {
             floor_height: i16::from_le_bytes(buf[0..2].try_into().unwrap()),
             ceiling_height: i16::from_le_bytes(buf[2..4].try_into().unwrap()),
-            floor_texture: buf_to_fstr(&buf[4..12])
-                .map_err(|_| format!("Sector {} has invalid floor texture", sector_id))?,
+            floor_texture: buf_to_fstr(&buf[4..12]).map_err(|_| invalid_name(entry, sector_id))?,
             ceiling_texture: buf_to_fstr(&buf[12..20])
-                .map_err(|_| format!("Sector {} has invalid ceiling texture", sector_id))?,
+                .map_err(|_| invalid_name(entry, sector_id))?,
             light_level: i16::from_le_bytes(buf[20..22].try_into().unwrap()),
             special: u16::from_le_bytes(buf[22..24].try_into().unwrap()),
             sector_tag: u16::from_le_bytes(buf[24..26].try_into().unwrap()),
@@ -246,25 +1250,27 @@ fn decode_sectors(file: &mut File, entry: &DirectoryEntry) -> Result<Vec<Sector>
     Ok(sectors)
 }
 
-fn decode_sidedefs(file: &mut File, entry: &DirectoryEntry) -> Result<Vec<SideDef>, String> {
+fn decode_sidedefs<R: Read + Seek>(
+    file: &mut R,
+    entry: &DirectoryEntry,
+) -> Result<Vec<SideDef>, WadError> {
     const SIDEDEF_SIZE: usize = 30; // can't use std::mem::size_of::<SideDef>() because it has fstr's rather than 8-byte character arrays, as in the WAD
-    assert!(entry.size % SIDEDEF_SIZE as i32 == 0);
-
-    file.seek(std::io::SeekFrom::Start(entry.offset as u64))
-        .map_err(|err| err.to_string())?;
+    let num_records = seek_to_records(file, entry, SIDEDEF_SIZE)?;
 
     let mut buf = [0; SIDEDEF_SIZE];
     let mut sidedefs = vec![];
-    for _ in 0..(entry.size / SIDEDEF_SIZE as i32) {
-        file.read_exact(&mut buf).map_err(|err| err.to_string())?;
+    for sidedef_id in 0..num_records {
+        read_record(file, entry, &mut buf)?;
 
         const NO_TEXTURE_PLACEHOLDER: &str = "-";
-        let upper_texture =
-            Some(buf_to_fstr(&buf[4..12])?).filter(|str| str != NO_TEXTURE_PLACEHOLDER);
-        let lower_texture =
-            Some(buf_to_fstr(&buf[12..20])?).filter(|str| str != NO_TEXTURE_PLACEHOLDER);
-        let middle_texture =
-            Some(buf_to_fstr(&buf[20..28])?).filter(|str| str != NO_TEXTURE_PLACEHOLDER);
+        let texture = |range: std::ops::Range<usize>| {
+            buf_to_fstr::<8>(&buf[range])
+                .map(|str| Some(str).filter(|str| str != NO_TEXTURE_PLACEHOLDER))
+                .map_err(|_| invalid_name(entry, sidedef_id))
+        };
+        let upper_texture = texture(4..12)?;
+        let lower_texture = texture(12..20)?;
+        let middle_texture = texture(20..28)?;
 
         sidedefs.push(SideDef {
             x: i16::from_le_bytes(buf[0..2].try_into().unwrap()),
@@ -278,17 +1284,17 @@ fn decode_sidedefs(file: &mut File, entry: &DirectoryEntry) -> Result<Vec<SideDe
     Ok(sidedefs)
 }
 
-fn decode_things(file: &mut File, entry: &DirectoryEntry) -> Result<Vec<Thing>, String> {
-    const THING_SIZE: usize = std::mem::size_of::<Thing>();
-    assert!(entry.size % THING_SIZE as i32 == 0);
-
-    file.seek(std::io::SeekFrom::Start(entry.offset as u64))
-        .map_err(|err| err.to_string())?;
+fn decode_things<R: Read + Seek>(
+    file: &mut R,
+    entry: &DirectoryEntry,
+) -> Result<Vec<Thing>, WadError> {
+    const THING_SIZE: usize = 10;
+    let num_records = seek_to_records(file, entry, THING_SIZE)?;
 
     let mut buf = [0; THING_SIZE];
     let mut things = vec![];
-    for _ in 0..(entry.size / THING_SIZE as i32) {
-        file.read_exact(&mut buf).map_err(|err| err.to_string())?;
+    for _ in 0..num_records {
+        read_record(file, entry, &mut buf)?;
 
         let ints: Vec<i16> = buf
             .chunks_exact(2)
@@ -306,17 +1312,49 @@ fn decode_things(file: &mut File, entry: &DirectoryEntry) -> Result<Vec<Thing>,
     Ok(things)
 }
 
-fn decode_vertexes(file: &mut File, entry: &DirectoryEntry) -> Result<Vec<Vertex>, String> {
-    const VERTEX_SIZE: usize = std::mem::size_of::<Vertex>();
-    assert!(entry.size % VERTEX_SIZE as i32 == 0);
+fn decode_hexen_things<R: Read + Seek>(
+    file: &mut R,
+    entry: &DirectoryEntry,
+) -> Result<Vec<HexenThing>, WadError> {
+    const HEXEN_THING_SIZE: usize = 20;
+    let num_records = seek_to_records(file, entry, HEXEN_THING_SIZE)?;
 
-    file.seek(std::io::SeekFrom::Start(entry.offset as u64))
-        .map_err(|err| err.to_string())?;
+    let mut buf = [0; HEXEN_THING_SIZE];
+    let mut things = vec![];
+    for _ in 0..num_records {
+        read_record(file, entry, &mut buf)?;
+
+        let ints: Vec<i16> = buf[0..14]
+            .chunks_exact(2)
+            .map(|c| c.try_into().map(i16::from_le_bytes).unwrap())
+            .collect();
+
+        things.push(HexenThing {
+            tid: ints[0],
+            x: ints[1],
+            y: ints[2],
+            z: ints[3],
+            angle: ints[4],
+            thing_type: ints[5],
+            flags: ints[6],
+            special: buf[14],
+            args: buf[15..20].try_into().unwrap(),
+        })
+    }
+    Ok(things)
+}
+
+fn decode_vertexes<R: Read + Seek>(
+    file: &mut R,
+    entry: &DirectoryEntry,
+) -> Result<Vec<Vertex>, WadError> {
+    const VERTEX_SIZE: usize = std::mem::size_of::<Vertex>();
+    let num_records = seek_to_records(file, entry, VERTEX_SIZE)?;
 
     let mut buf = [0; VERTEX_SIZE];
     let mut vertexes = vec![];
-    for _ in 0..(entry.size / VERTEX_SIZE as i32) {
-        file.read_exact(&mut buf).map_err(|err| err.to_string())?;
+    for _ in 0..num_records {
+        read_record(file, entry, &mut buf)?;
 
         vertexes.push(Vertex {
             x: i16::from_le_bytes(buf[0..2].try_into().unwrap()),
@@ -326,71 +1364,283 @@ fn decode_vertexes(file: &mut File, entry: &DirectoryEntry) -> Result<Vec<Vertex
     Ok(vertexes)
 }
 
-fn decode_lumps<T>(
-    file: &mut File,
+type Decoder<T> = fn(&mut Cursor<Vec<u8>>, &DirectoryEntry) -> Result<Vec<T>, WadError>;
+
+// Map lumps are read whole and decoded from memory, so that lumps which have been replaced in
+// memory decode just like the ones in the source
+fn decode_records<T>(
+    entry: &DirectoryEntry,
+    bytes: Vec<u8>,
+    decoder_fn: Decoder<T>,
+) -> Result<Vec<T>, WadError> {
+    let entry = DirectoryEntry {
+        offset: 0,
+        data: None,
+        ..entry.clone()
+    };
+    decoder_fn(&mut Cursor::new(bytes), &entry)
+}
+
+fn decode_lumps<R: Read + Seek, T>(
+    file: &mut R,
     lumps_map: &HashMap<String, DirectoryEntry>,
     lump_type: &str,
-    decoder_fn: fn(&mut File, &DirectoryEntry) -> Result<Vec<T>, String>,
-) -> Result<Vec<T>, String> {
-    let lumps = lumps_map
-        .get(lump_type)
-        .ok_or(format!("No {} lumps found", lump_type))?;
-    println!("lump type {} lumps {:?}", lump_type, lumps);
-    Ok(decoder_fn(file, lumps)?)
-}
-
-fn decode_maps(file: &mut File, directory: &Vec<DirectoryEntry>) -> Result<Vec<MapData>, String> {
-    let map_lump_names: Vec<String> = vec![
-        "BLOCKMAP", "LINEDEFS", "NODES", "REJECT", "SCRIPTS", "SECTORS", "SEGS", "SIDEDEFS",
-        "SSECTORS", "THINGS", "VERTEXES",
-    ]
-    .iter()
-    .map(|str| str.to_string())
-    .collect();
-
-    // Collect all of the lumps on a per-map basis
-    // TODO: I don't love this code; revisit it
-    let mut map_lumps: HashMap<String, HashMap<String, DirectoryEntry>> = HashMap::new();
-    for mut i in 0..directory.len() {
-        let d = directory.get(i).unwrap();
-        if d.size == 0 && d.offset > 0 {
-            // this lump is the start of a map
-            let map_name = d.name.clone();
-            let mut lumps = HashMap::new();
+    decoder_fn: Decoder<T>,
+) -> Result<Vec<T>, WadError> {
+    let entry = lumps_map.get(lump_type).ok_or(WadError::MissingLump {
+        lump: lump_type.to_string(),
+    })?;
+    decode_records(entry, read_lump(file, entry)?, decoder_fn)
+}
 
-            loop {
-                i += 1;
-                let d = directory.get(i).unwrap();
-                if !map_lump_names.contains(&d.name) {
-                    break;
-                }
-                lumps.insert(d.name.to_string(), d.clone());
-            }
+fn decode_map<R: Read + Seek>(
+    file: &mut R,
+    map_name: &str,
+    lumps: &HashMap<String, DirectoryEntry>,
+) -> Result<MapData, WadError> {
+    if let Some(textmap_entry) = lumps.get("TEXTMAP") {
+        return decode_udmf_map(file, map_name, textmap_entry, lumps);
+    }
+
+    let (format, linedefs, things) = match lumps.get("BEHAVIOR") {
+        Some(behavior_entry) => {
+            let hexen_linedefs = decode_lumps(file, lumps, "LINEDEFS", decode_hexen_linedefs)?;
+            let hexen_things = decode_lumps(file, lumps, "THINGS", decode_hexen_things)?;
+            let behavior = read_lump(file, behavior_entry)?;
+
+            let linedefs = hexen_linedefs
+                .iter()
+                .map(HexenLineDef::to_linedef)
+                .collect();
+            let things = hexen_things.iter().map(HexenThing::to_thing).collect();
+
+            let format = MapFormat::Hexen {
+                linedefs: hexen_linedefs,
+                things: hexen_things,
+                behavior,
+            };
+            (format, linedefs, things)
+        }
+        None => (
+            MapFormat::Doom,
+            decode_lumps(file, lumps, "LINEDEFS", decode_linedefs)?,
+            decode_lumps(file, lumps, "THINGS", decode_things)?,
+        ),
+    };
+    let vertexes = decode_lumps(file, lumps, "VERTEXES", decode_vertexes)?;
+    let sidedefs = decode_lumps(file, lumps, "SIDEDEFS", decode_sidedefs)?;
+    let sectors = decode_lumps(file, lumps, "SECTORS", decode_sectors)?;
+    let (nodes, segs, subsectors) = decode_bsp(file, lumps)?;
+    let blockmap = match lumps.get("BLOCKMAP") {
+        Some(entry) if entry.size > 0 => Some(decode_blockmap(entry, &read_lump(file, entry)?)?),
+        _ => None,
+    };
+    let reject = decode_reject(file, lumps, sectors.len())?;
+
+    Ok(MapData {
+        name: map_name.to_string(),
+        format,
+        linedefs,
+        sectors,
+        sidedefs,
+        things,
+        vertexes,
+        nodes,
+        segs,
+        subsectors,
+        blockmap,
+        reject,
+    })
+}
+
+fn decode_reject<R: Read + Seek>(
+    file: &mut R,
+    lumps: &HashMap<String, DirectoryEntry>,
+    num_sectors: usize,
+) -> Result<RejectTable, WadError> {
+    match lumps.get("REJECT") {
+        Some(entry) => Ok(RejectTable::new(num_sectors, read_lump(file, entry)?)),
+        None => Ok(RejectTable::missing(num_sectors)),
+    }
+}
+
+type Bsp = (Vec<Node>, Vec<Seg>, Vec<SubSector>);
+
+fn decode_bsp<R: Read + Seek>(
+    file: &mut R,
+    lumps: &HashMap<String, DirectoryEntry>,
+) -> Result<Bsp, WadError> {
+    let (Some(nodes_entry), Some(_), Some(_)) =
+        (lumps.get("NODES"), lumps.get("SEGS"), lumps.get("SSECTORS"))
+    else {
+        return Ok((vec![], vec![], vec![]));
+    };
+
+    // ZDoom's extended and compressed node formats are stored in the NODES lump, and identified
+    // by a four byte signature (XNOD, ZNOD, XGLN, etc.); we only understand vanilla nodes
+    let nodes = read_lump(file, nodes_entry)?;
+    if nodes.len() >= 4 && matches!(&nodes[1..4], b"NOD" | b"GLN" | b"GL2" | b"GL3") {
+        return Ok((vec![], vec![], vec![]));
+    }
 
-            map_lumps.insert(map_name, lumps);
+    Ok((
+        decode_records(nodes_entry, nodes, decode_nodes)?,
+        decode_lumps(file, lumps, "SEGS", decode_segs)?,
+        decode_lumps(file, lumps, "SSECTORS", decode_subsectors)?,
+    ))
+}
+
+fn decode_udmf_map<R: Read + Seek>(
+    file: &mut R,
+    map_name: &str,
+    textmap_entry: &DirectoryEntry,
+    lumps: &HashMap<String, DirectoryEntry>,
+) -> Result<MapData, WadError> {
+    let textmap = read_lump(file, textmap_entry)?;
+    let udmf = UdmfMap::parse(&String::from_utf8_lossy(&textmap))?;
+    let reject = decode_reject(file, lumps, udmf.sectors.len())?;
+
+    Ok(MapData {
+        name: map_name.to_string(),
+        linedefs: udmf.to_linedefs(),
+        sectors: udmf.to_sectors(),
+        sidedefs: udmf.to_sidedefs(),
+        things: udmf.to_things(),
+        vertexes: udmf.to_vertexes(),
+        // UDMF maps keep their nodes in ZNODES, which is in one of ZDoom's node formats
+        nodes: vec![],
+        segs: vec![],
+        subsectors: vec![],
+        blockmap: None,
+        reject,
+        format: MapFormat::Udmf(udmf),
+    })
+}
+
+// The lumps that can follow a map marker in a binary (Doom- or Hexen-format) map
+const MAP_LUMP_NAMES: [&str; 12] = [
+    "BEHAVIOR", "BLOCKMAP", "LINEDEFS", "NODES", "REJECT", "SCRIPTS", "SECTORS", "SEGS",
+    "SIDEDEFS", "SSECTORS", "THINGS", "VERTEXES",
+];
+
+// Map markers can have any name, and aren't necessarily empty, so the only reliable way to spot
+// one is by the lumps that follow it. Returns those lumps if the lump at `marker_index` is a map
+// marker.
+fn lumps_after_map_marker(
+    directory: &[DirectoryEntry],
+    marker_index: usize,
+) -> Option<&[DirectoryEntry]> {
+    let following = directory.get(marker_index + 1..)?;
+
+    if following.first()?.name == "TEXTMAP" {
+        // UDMF maps can contain any lumps at all, up until their ENDMAP marker
+        let end = following
+            .iter()
+            .position(|d| d.name == "ENDMAP")
+            .unwrap_or(following.len());
+        return Some(&following[..end]);
+    }
+
+    let end = following
+        .iter()
+        .position(|d| !MAP_LUMP_NAMES.contains(&d.name.as_str()))
+        .unwrap_or(following.len());
+    let lumps = &following[..end];
+    lumps
+        .iter()
+        .any(|d| d.name == "THINGS" || d.name == "LINEDEFS")
+        .then_some(lumps)
+}
+
+struct DecodedMaps {
+    maps: Vec<MapData>,
+    // The directory index of each map's marker, so that its lumps can be found again even if
+    // another map has the same name
+    markers: Vec<usize>,
+    // Each one is a `WadError::InMap`, so it says which map it belongs to
+    errors: Vec<WadError>,
+}
+
+fn decode_maps<R: Read + Seek>(file: &mut R, directory: &[DirectoryEntry]) -> DecodedMaps {
+    // Find each map's marker; when a map name appears more than once, the last one wins
+    let mut map_markers: HashMap<&str, usize> = HashMap::new();
+    let mut i = 0;
+    while i < directory.len() {
+        match lumps_after_map_marker(directory, i) {
+            Some(lumps) => {
+                map_markers.insert(&directory[i].name, i);
+                i += 1 + lumps.len();
+            }
+            None => i += 1,
         }
     }
 
     // Create MapData instances based on the lumps
-    let mut maps: Vec<MapData> = vec![];
-    for (map_name, lumps) in &map_lumps {
-        let linedefs = decode_lumps(file, lumps, "LINEDEFS", decode_linedefs)?;
-        let things = decode_lumps(file, lumps, "THINGS", decode_things)?;
-        let vertexes = decode_lumps(file, lumps, "VERTEXES", decode_vertexes)?;
-        let sidedefs = decode_lumps(file, lumps, "SIDEDEFS", decode_sidedefs)?;
-        let sectors = decode_lumps(file, lumps, "SECTORS", decode_sectors)?;
-
-        maps.push(MapData {
-            name: map_name.to_string(),
-            linedefs,
-            sectors,
-            sidedefs,
-            things,
-            vertexes,
-        })
+    let mut decoded = DecodedMaps {
+        maps: vec![],
+        markers: vec![],
+        errors: vec![],
+    };
+    let mut map_names: Vec<&str> = map_markers.keys().copied().collect();
+    map_names.sort();
+    for map_name in map_names {
+        let marker_index = map_markers[map_name];
+        let lumps = lumps_after_map_marker(directory, marker_index)
+            .unwrap_or_default()
+            .iter()
+            .map(|d| (d.name.to_string(), d.clone()))
+            .collect();
+        match decode_map(file, map_name, &lumps) {
+            Ok(map) => {
+                decoded.maps.push(map);
+                decoded.markers.push(marker_index);
+            }
+            Err(err) => decoded.errors.push(err.in_map(map_name)),
+        }
     }
 
-    maps.sort_by_key(|map| map.name.clone());
+    decoded
+}
+
+// Like seek_to_records, but for lumps that have already been read into memory
+fn split_records<'a>(
+    lump: &str,
+    bytes: &'a [u8],
+    record_size: usize,
+) -> Result<std::slice::ChunksExact<'a, u8>, WadError> {
+    if !bytes.len().is_multiple_of(record_size) {
+        return Err(WadError::LumpSizeNotMultiple {
+            lump: lump.to_string(),
+            size: bytes.len() as i32,
+            record_size,
+        });
+    }
+
+    Ok(bytes.chunks_exact(record_size))
+}
+
+fn decode_playpal(entry: &DirectoryEntry, bytes: &[u8]) -> Result<Vec<Palette>, WadError> {
+    // https://doomwiki.org/wiki/PLAYPAL
+    // Each palette is 256 RGB triples. The IWADs have 14 of them, but some PWADs replace PLAYPAL
+    // with fewer (or more), and nothing but the palette effects cares.
+
+    Ok(split_records(&entry.name, bytes, 256 * 3)?
+        .map(|palette_buf| {
+            let mut colors = [[0; 3]; 256];
+            for (color, rgb) in colors.iter_mut().zip(palette_buf.chunks_exact(3)) {
+                color.copy_from_slice(rgb);
+            }
+            Palette { colors }
+        })
+        .collect())
+}
+
+fn decode_colormap(entry: &DirectoryEntry, bytes: &[u8]) -> Result<Colormap, WadError> {
+    // https://doomwiki.org/wiki/COLORMAP
+    // 34 tables of 256 palette indexes each. Boom-style custom colormaps have the same layout.
 
-    return Ok(maps);
+    let tables = split_records(&entry.name, bytes, 256)?
+        .map(|table_buf| table_buf.try_into().unwrap())
+        .collect();
+    Ok(Colormap { tables })
 }
@@ -0,0 +1,237 @@
+// Demos (.lmp files, and the DEMO1 to DEMO4 lumps) are recordings of a game: a header describing
+// how the game was started, followed by every player's input for every tic. The game plays them
+// back by simulating the same inputs, so nothing else is stored. See
+// https://doomwiki.org/wiki/Demo#Technical_information
+
+use super::{MapData, Wad, WadError, WadStack};
+
+#[derive(Clone, Debug)]
+pub struct Demo {
+    // 104 to 111 for Doom 1.4 to 1.10, 200 and up for Boom and its descendants; None for demos
+    // recorded before Doom 1.4, which don't record one
+    pub version: Option<u8>,
+    // 0 to 4, for "I'm too young to die" to "Nightmare!"
+    pub skill: u8,
+    // Always 1 in Doom II
+    pub episode: u8,
+    pub map: u8,
+    // 0 for cooperative, 1 for deathmatch, 2 for altdeath
+    pub deathmatch: u8,
+    pub respawn: bool,
+    pub fast: bool,
+    pub no_monsters: bool,
+    // The player whose view the demo was recorded from
+    pub console_player: u8,
+    // Which player slots were in the game; Boom records 32 of them, vanilla Doom 4
+    pub players: Vec<bool>,
+    // One entry per tic, with one command for each player in the game, in player order
+    pub tics: Vec<Vec<TicCmd>>,
+}
+
+// A player's input for one tic
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct TicCmd {
+    // Positive is forwards
+    pub forward_move: i8,
+    // Positive is to the right
+    pub side_move: i8,
+    // In 1/65536ths of a full turn, positive is to the left. Most demos only record the top byte.
+    pub angle_turn: i16,
+    pub buttons: u8,
+}
+
+impl TicCmd {
+    pub const ATTACK: u8 = 0x01;
+    pub const USE: u8 = 0x02;
+    // The weapon number in the next three bits is only used if this is set
+    pub const CHANGE: u8 = 0x04;
+    // Pause and save game commands, which replace the other buttons
+    pub const SPECIAL: u8 = 0x80;
+
+    // The weapon the player switched to, numbered the way the game does internally: 0 for the fist
+    // up to 7 for the chainsaw. The super shotgun is selected by asking for the shotgun again.
+    pub fn weapon_change(&self) -> Option<u8> {
+        if self.buttons & TicCmd::SPECIAL == 0 && self.buttons & TicCmd::CHANGE != 0 {
+            Some((self.buttons >> 3) & 0x07)
+        } else {
+            None
+        }
+    }
+}
+
+const DEMO_END_MARKER: u8 = 0x80;
+
+impl Demo {
+    pub const TICS_PER_SECOND: u32 = 35;
+
+    pub fn decode(lump: &str, bytes: &[u8]) -> Result<Demo, WadError> {
+        let invalid = |message: &str| WadError::InvalidLump {
+            lump: lump.to_string(),
+            message: message.to_string(),
+        };
+        let header = |len: usize| {
+            bytes
+                .get(..len)
+                .ok_or_else(|| invalid("too short to hold a demo header"))
+        };
+
+        let version = *bytes.first().ok_or_else(|| invalid("demo is empty"))?;
+        let (mut demo, tics_start, long_tics) = match version {
+            // Before Doom 1.4 the header starts with the skill level, and there's no version
+            0..=4 => {
+                let buf = header(7)?;
+                let demo = Demo {
+                    version: None,
+                    skill: buf[0],
+                    episode: buf[1],
+                    map: buf[2],
+                    deathmatch: 0,
+                    respawn: false,
+                    fast: false,
+                    no_monsters: false,
+                    console_player: 0,
+                    players: buf[3..7].iter().map(|&b| b != 0).collect(),
+                    tics: vec![],
+                };
+                (demo, 7, false)
+            }
+            104..=111 => {
+                let buf = header(13)?;
+                let demo = Demo {
+                    version: Some(version),
+                    skill: buf[1],
+                    episode: buf[2],
+                    map: buf[3],
+                    deathmatch: buf[4],
+                    respawn: buf[5] != 0,
+                    fast: buf[6] != 0,
+                    no_monsters: buf[7] != 0,
+                    console_player: buf[8],
+                    players: buf[9..13].iter().map(|&b| b != 0).collect(),
+                    tics: vec![],
+                };
+                // Version 111 is used by source ports for -longtics demos, which record turning
+                // at full precision
+                (demo, 13, version == 111)
+            }
+            200..=214 => {
+                // The version, a signature ("\x1DBoom\xE6" or "\x1DMBF\xE6\0"), the old
+                // compatibility flag, the game settings, 64 bytes of game options (256 in Boom
+                // 2.00) and 32 player slots
+                let options_size = if version == 200 { 256 } else { 64 };
+                let players_start = 13 + options_size;
+                let buf = header(players_start + 32)?;
+                if buf[1] != 0x1D {
+                    return Err(invalid("Boom demo has an unknown signature"));
+                }
+                let options = &buf[13..players_start];
+                let demo = Demo {
+                    version: Some(version),
+                    skill: buf[8],
+                    episode: buf[9],
+                    map: buf[10],
+                    deathmatch: buf[11],
+                    respawn: options[6] != 0,
+                    fast: options[7] != 0,
+                    no_monsters: options[8] != 0,
+                    console_player: buf[12],
+                    players: buf[players_start..].iter().map(|&b| b != 0).collect(),
+                    tics: vec![],
+                };
+                // PrBoom+ always records -longtics demos as version 214
+                (demo, players_start + 32, version == 214)
+            }
+            _ => return Err(invalid("demo has an unsupported version")),
+        };
+
+        let player_count = demo.players.iter().filter(|&&in_game| in_game).count();
+        if player_count == 0 {
+            return Err(invalid("demo has no players"));
+        }
+        let cmd_size = if long_tics { 5 } else { 4 };
+
+        // The tics run until the end marker. Anything after that (such as the footers some source
+        // ports add) isn't part of the recording.
+        let mut pos = tics_start;
+        loop {
+            match bytes.get(pos) {
+                Some(&DEMO_END_MARKER) => break,
+                // Some demos were cut off without an end marker, which the game treats the same
+                None => break,
+                Some(_) => {}
+            }
+            let buf = bytes
+                .get(pos..pos + player_count * cmd_size)
+                .ok_or_else(|| invalid("demo ends in the middle of a tic"))?;
+            let tic = buf
+                .chunks_exact(cmd_size)
+                .map(|cmd| TicCmd {
+                    forward_move: cmd[0] as i8,
+                    side_move: cmd[1] as i8,
+                    angle_turn: if long_tics {
+                        i16::from_le_bytes([cmd[2], cmd[3]])
+                    } else {
+                        i16::from_le_bytes([0, cmd[2]])
+                    },
+                    buttons: cmd[cmd_size - 1],
+                })
+                .collect();
+            demo.tics.push(tic);
+            pos += player_count * cmd_size;
+        }
+
+        Ok(demo)
+    }
+
+    // The slot numbers of the players in the game, counting from 0
+    pub fn player_numbers(&self) -> Vec<usize> {
+        self.players
+            .iter()
+            .enumerate()
+            .filter(|(_, &in_game)| in_game)
+            .map(|(number, _)| number)
+            .collect()
+    }
+
+    pub fn duration_seconds(&self) -> f64 {
+        self.tics.len() as f64 / Demo::TICS_PER_SECOND as f64
+    }
+
+    // The demo doesn't say which game it's for, so its map could be either of these
+    pub fn map_names(&self) -> [String; 2] {
+        [
+            format!("E{}M{}", self.episode, self.map),
+            format!("MAP{:02}", self.map),
+        ]
+    }
+}
+
+impl Wad {
+    pub fn demo(&self, name: &str) -> Result<Demo, WadError> {
+        let entry = self.find_lump(name).ok_or(WadError::MissingLump {
+            lump: name.to_string(),
+        })?;
+        Demo::decode(&entry.name, &self.lump_bytes(entry)?)
+    }
+
+    pub fn demo_map(&self, demo: &Demo) -> Option<&MapData> {
+        demo.map_names().iter().find_map(|name| {
+            self.maps()
+                .iter()
+                .find(|map| map.name.eq_ignore_ascii_case(name))
+        })
+    }
+}
+
+impl WadStack {
+    pub fn demo(&self, name: &str) -> Result<Demo, WadError> {
+        let lump = self.find_lump(name).ok_or(WadError::MissingLump {
+            lump: name.to_string(),
+        })?;
+        Demo::decode(lump.name(), &lump.bytes()?)
+    }
+
+    pub fn demo_map(&self, demo: &Demo) -> Option<&MapData> {
+        demo.map_names().iter().find_map(|name| self.map(name))
+    }
+}
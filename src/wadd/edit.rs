@@ -0,0 +1,141 @@
+// Operations for changing the contents of a Wad's directory. Changes only exist in memory until
+// the Wad is written out with `Wad::save`.
+//
+// Decoded maps would go stale as the directory changes underneath them, so each of these writes
+// any edits to the maps into their lumps first, and has the maps decoded again the next time
+// they're asked for.
+
+use std::cell::OnceCell;
+
+use super::{DirectoryEntry, Wad, WadError};
+
+impl Wad {
+    pub fn insert_lump(&mut self, index: usize, name: &str, data: Vec<u8>) -> Result<(), WadError> {
+        validate_lump_name(name)?;
+        self.check_index(index, self.directory.len() + 1)?;
+
+        self.flush_map_edits()?;
+        self.directory.insert(index, new_entry(name, data));
+        Ok(())
+    }
+
+    pub fn append_lump(&mut self, name: &str, data: Vec<u8>) -> Result<usize, WadError> {
+        let index = self.directory.len();
+        self.insert_lump(index, name, data)?;
+        Ok(index)
+    }
+
+    // Adds a lump to the end of a namespace, such as "F" for the flats between F_START and F_END
+    // or "S" for sprites. PWADs often use doubled markers (FF_START/FF_END), which are recognized
+    // too. If the namespace doesn't exist yet, its markers are added to the end of the directory.
+    pub fn insert_in_namespace(
+        &mut self,
+        namespace: &str,
+        name: &str,
+        data: Vec<u8>,
+    ) -> Result<usize, WadError> {
+        validate_lump_name(name)?;
+
+        let end_markers = [
+            format!("{}_END", namespace),
+            format!("{}{}_END", namespace, namespace),
+        ];
+        let end_index = self.directory.iter().rposition(|entry| {
+            end_markers
+                .iter()
+                .any(|marker| entry.name.eq_ignore_ascii_case(marker))
+        });
+
+        let index = match end_index {
+            Some(end_index) => end_index,
+            None => {
+                let start_marker = format!("{}_START", namespace);
+                validate_lump_name(&start_marker)?;
+                self.append_lump(&start_marker, vec![])?;
+                self.append_lump(&end_markers[0], vec![])?
+            }
+        };
+        self.insert_lump(index, name, data)?;
+        Ok(index)
+    }
+
+    pub fn replace_lump(&mut self, index: usize, data: Vec<u8>) -> Result<(), WadError> {
+        self.check_index(index, self.directory.len())?;
+
+        self.flush_map_edits()?;
+        let entry = &mut self.directory[index];
+        entry.size = data.len() as i32;
+        entry.data = Some(data);
+        Ok(())
+    }
+
+    // Renaming a map's marker renames the map along with it
+    pub fn rename_lump(&mut self, index: usize, new_name: &str) -> Result<(), WadError> {
+        validate_lump_name(new_name)?;
+        self.check_index(index, self.directory.len())?;
+
+        self.flush_map_edits()?;
+        self.directory[index].name = new_name.to_string();
+        Ok(())
+    }
+
+    pub fn remove_lump(&mut self, index: usize) -> Result<DirectoryEntry, WadError> {
+        self.check_index(index, self.directory.len())?;
+
+        self.flush_map_edits()?;
+        Ok(self.directory.remove(index))
+    }
+
+    // Moves a lump so that it ends up at `new_index` once it has been removed from its old spot
+    pub fn move_lump(&mut self, index: usize, new_index: usize) -> Result<(), WadError> {
+        self.check_index(index, self.directory.len())?;
+        self.check_index(new_index, self.directory.len())?;
+
+        self.flush_map_edits()?;
+        let entry = self.directory.remove(index);
+        self.directory.insert(new_index, entry);
+        Ok(())
+    }
+
+    fn flush_map_edits(&mut self) -> Result<(), WadError> {
+        for (index, data) in self.encode_map_lumps()? {
+            let entry = &mut self.directory[index];
+            entry.size = data.len() as i32;
+            entry.data = Some(data);
+        }
+        self.decoded_maps = OnceCell::new();
+        Ok(())
+    }
+
+    fn check_index(&self, index: usize, limit: usize) -> Result<(), WadError> {
+        if index >= limit {
+            return Err(WadError::LumpIndexOutOfBounds {
+                index,
+                num_lumps: self.directory.len(),
+            });
+        }
+        Ok(())
+    }
+}
+
+fn new_entry(name: &str, data: Vec<u8>) -> DirectoryEntry {
+    DirectoryEntry {
+        name: name.to_string(),
+        offset: 0,
+        size: data.len() as i32,
+        data: Some(data),
+    }
+}
+
+// Doom itself looks lumps up by their uppercased name, so lowercase names would never be found
+pub fn validate_lump_name(name: &str) -> Result<(), WadError> {
+    let is_valid_char =
+        |c: char| c.is_ascii_uppercase() || c.is_ascii_digit() || "[]-_\\^".contains(c);
+    if name.is_empty() || name.len() > 8 || !name.chars().all(is_valid_char) {
+        return Err(WadError::InvalidName {
+            lump: name.to_string(),
+            index: None,
+        });
+    }
+    Ok(())
+}
@@ -0,0 +1,176 @@
+// Flats are the floor and ceiling textures: raw, unpadded palette indexes with no header, found
+// between F_START and F_END. See https://doomwiki.org/wiki/Flat
+
+use super::{buf_to_string, split_records, Lump, Palette, Wad, WadError, WadStack};
+
+#[derive(Clone, Debug)]
+pub struct Flat {
+    pub width: u16,
+    pub height: u16,
+    // Palette indexes, row by row
+    pub pixels: Vec<u8>,
+}
+
+impl Flat {
+    pub fn decode(lump: &str, bytes: &[u8]) -> Result<Flat, WadError> {
+        // Doom's flats are always 64x64. Heretic and Hexen have a few 64x128 ones, and source ports
+        // allow bigger square flats.
+        let (width, height) = match bytes.len() {
+            4096 => (64, 64),
+            8192 => (64, 128),
+            len => {
+                let side = (len as f64).sqrt() as usize;
+                if side == 0 || side * side != len || side > u16::MAX as usize {
+                    return Err(WadError::InvalidLump {
+                        lump: lump.to_string(),
+                        message: format!("a {} byte lump is not a valid flat size", len),
+                    });
+                }
+                (side as u16, side as u16)
+            }
+        };
+
+        Ok(Flat {
+            width,
+            height,
+            pixels: bytes.to_vec(),
+        })
+    }
+
+    // 4 bytes per pixel, row by row
+    pub fn to_rgba(&self, palette: &Palette) -> Vec<u8> {
+        self.pixels
+            .iter()
+            .flat_map(|&index| palette.rgba(index))
+            .collect()
+    }
+}
+
+// A range of flats that the game cycles through, such as NUKAGE1 to NUKAGE3. Which flats are in
+// the range depends on their order in the WAD, not their names.
+#[derive(Clone, Debug)]
+pub struct FlatAnimation {
+    pub frames: Vec<String>,
+    pub tics_per_frame: i32,
+}
+
+// Doom's animations are hardcoded as (first, last) pairs in p_spec.c; they all change every 8 tics
+const DOOM_FLAT_ANIMATIONS: [(&str, &str); 9] = [
+    ("NUKAGE1", "NUKAGE3"),
+    ("FWATER1", "FWATER4"),
+    ("SWATER1", "SWATER4"),
+    ("LAVA1", "LAVA4"),
+    ("BLOOD1", "BLOOD3"),
+    ("RROCK05", "RROCK08"),
+    ("SLIME01", "SLIME04"),
+    ("SLIME05", "SLIME08"),
+    ("SLIME09", "SLIME12"),
+];
+
+impl Wad {
+    pub fn flat(&self, name: &str) -> Result<Flat, WadError> {
+        find_flat(&self.namespace_lumps("F"), name)
+    }
+
+    pub fn flat_animations(&self) -> Result<Vec<FlatAnimation>, WadError> {
+        let animated = match self.find_lump("ANIMATED") {
+            Some(entry) => Some(decode_animated(&entry.name, &self.lump_bytes(entry)?)?),
+            None => None,
+        };
+        Ok(flat_animations(&self.namespace_lumps("F"), animated))
+    }
+}
+
+impl WadStack {
+    pub fn flat(&self, name: &str) -> Result<Flat, WadError> {
+        find_flat(&self.namespace_lumps("F"), name)
+    }
+
+    pub fn flat_animations(&self) -> Result<Vec<FlatAnimation>, WadError> {
+        let animated = match self.find_lump("ANIMATED") {
+            Some(lump) => Some(decode_animated(lump.name(), &lump.bytes()?)?),
+            None => None,
+        };
+        Ok(flat_animations(&self.namespace_lumps("F"), animated))
+    }
+}
+
+fn find_flat(flats: &[Lump], name: &str) -> Result<Flat, WadError> {
+    let lump = flats
+        .iter()
+        .rev()
+        .find(|lump| lump.name().eq_ignore_ascii_case(name))
+        .ok_or(WadError::MissingLump {
+            lump: name.to_string(),
+        })?;
+    Flat::decode(lump.name(), &lump.bytes()?)
+}
+
+struct AnimationDef {
+    is_texture: bool,
+    first: String,
+    last: String,
+    tics_per_frame: i32,
+}
+
+// Boom's ANIMATED lump replaces the hardcoded animations with a list of 23 byte records, ending
+// with a record whose type is 255. See https://doomwiki.org/wiki/ANIMATED
+fn decode_animated(lump: &str, bytes: &[u8]) -> Result<Vec<AnimationDef>, WadError> {
+    let invalid_name = |index| WadError::InvalidName {
+        lump: lump.to_string(),
+        index: Some(index),
+    };
+
+    let mut defs = vec![];
+    let end = bytes
+        .iter()
+        .step_by(23)
+        .position(|&kind| kind == 0xFF)
+        .map_or(bytes.len(), |index| index * 23);
+    for (index, record) in split_records(lump, &bytes[..end], 23)?.enumerate() {
+        defs.push(AnimationDef {
+            // Bit 0 is set for textures; ZDoom uses the other bits for its own flags
+            is_texture: record[0] & 1 != 0,
+            last: buf_to_string(&record[1..10]).map_err(|_| invalid_name(index))?,
+            first: buf_to_string(&record[10..19]).map_err(|_| invalid_name(index))?,
+            tics_per_frame: i32::from_le_bytes(record[19..23].try_into().unwrap()),
+        });
+    }
+    Ok(defs)
+}
+
+// Like the game, animations whose first flat is missing are skipped (the shareware IWAD doesn't
+// have them all), as are ones whose last flat doesn't come after the first
+fn flat_animations(flats: &[Lump], animated: Option<Vec<AnimationDef>>) -> Vec<FlatAnimation> {
+    let defs = animated.unwrap_or_else(|| {
+        DOOM_FLAT_ANIMATIONS
+            .iter()
+            .map(|&(first, last)| AnimationDef {
+                is_texture: false,
+                first: first.to_string(),
+                last: last.to_string(),
+                tics_per_frame: 8,
+            })
+            .collect()
+    });
+
+    let position = |name: &str| {
+        flats
+            .iter()
+            .rposition(|lump| lump.name().eq_ignore_ascii_case(name))
+    };
+    defs.iter()
+        .filter(|def| !def.is_texture)
+        .filter_map(|def| {
+            let first = position(&def.first)?;
+            let last = position(&def.last)?;
+            (first < last).then(|| FlatAnimation {
+                frames: flats[first..=last]
+                    .iter()
+                    .map(|lump| lump.name().to_string())
+                    .collect(),
+                tics_per_frame: def.tics_per_frame,
+            })
+        })
+        .collect()
+}
@@ -0,0 +1,280 @@
+// GENMIDI holds the OPL2 FM synthesis patches that DMX uses to play music on AdLib and Sound Blaster
+// cards: one for each of the 128 General MIDI instruments, then 47 for the percussion notes 35 to
+// 81. The lump is in the same format as DMX's OP2 bank files. See https://doomwiki.org/wiki/GENMIDI
+
+use std::fmt::Write;
+
+use super::{buf_to_string, Wad, WadError, WadStack};
+
+#[derive(Clone, Debug)]
+pub struct GenMidi {
+    pub instruments: Vec<OplInstrument>,
+}
+
+#[derive(Clone, Debug)]
+pub struct OplInstrument {
+    pub name: String,
+    pub flags: u16,
+    // Used to detune the second voice; 128 is in tune
+    pub fine_tuning: u8,
+    // The note always played by fixed pitch instruments, such as percussion
+    pub fixed_note: u8,
+    pub voices: [OplVoice; 2],
+}
+
+#[derive(Clone, Debug)]
+pub struct OplVoice {
+    pub modulator: OplOperator,
+    // The OPL's feedback/connection register
+    pub feedback: u8,
+    pub carrier: OplOperator,
+    // Added to every note played with this voice
+    pub base_note_offset: i16,
+}
+
+// The values written to each of an operator's OPL registers
+#[derive(Clone, Debug)]
+pub struct OplOperator {
+    // Tremolo, vibrato, sustain, key scale rate and frequency multiplier
+    pub characteristic: u8,
+    pub attack_decay: u8,
+    pub sustain_release: u8,
+    pub waveform: u8,
+    // The top two bits of the key scale/output level register
+    pub key_scale: u8,
+    pub output_level: u8,
+}
+
+impl OplInstrument {
+    pub const FIXED_PITCH: u16 = 0x0001;
+    pub const DOUBLE_VOICE: u16 = 0x0004;
+
+    pub fn is_fixed_pitch(&self) -> bool {
+        self.flags & OplInstrument::FIXED_PITCH != 0
+    }
+
+    // Instruments only use their second voice if this is set
+    pub fn is_double_voice(&self) -> bool {
+        self.flags & OplInstrument::DOUBLE_VOICE != 0
+    }
+
+    // A Sound Blaster Instrument file with the instrument's first voice, since SBI can't hold two
+    pub fn to_sbi(&self) -> Vec<u8> {
+        let voice = &self.voices[0];
+        let (modulator, carrier) = (&voice.modulator, &voice.carrier);
+
+        let mut buf = b"SBI\x1A".to_vec();
+        let mut name = [0; 32];
+        let len = self.name.len().min(31);
+        name[..len].copy_from_slice(&self.name.as_bytes()[..len]);
+        buf.extend_from_slice(&name);
+        buf.extend_from_slice(&[
+            modulator.characteristic,
+            carrier.characteristic,
+            modulator.key_scale | modulator.output_level,
+            carrier.key_scale | carrier.output_level,
+            modulator.attack_decay,
+            carrier.attack_decay,
+            modulator.sustain_release,
+            carrier.sustain_release,
+            modulator.waveform,
+            carrier.waveform,
+            voice.feedback,
+        ]);
+        // Reserved
+        buf.extend_from_slice(&[0; 5]);
+        buf
+    }
+}
+
+const GENMIDI_SIGNATURE: &[u8; 8] = b"#OPL_II#";
+const INSTRUMENT_COUNT: usize = 175;
+const INSTRUMENT_SIZE: usize = 36;
+const NAME_SIZE: usize = 32;
+
+impl GenMidi {
+    // The first percussion instrument is for this note; the rest follow in order
+    pub const FIRST_PERCUSSION_NOTE: u8 = 35;
+
+    pub fn decode(lump: &str, bytes: &[u8]) -> Result<GenMidi, WadError> {
+        // The signature, then every instrument's patch data, then every instrument's name
+        let names_start = 8 + INSTRUMENT_COUNT * INSTRUMENT_SIZE;
+        if !bytes.starts_with(GENMIDI_SIGNATURE) {
+            return Err(WadError::InvalidLump {
+                lump: lump.to_string(),
+                message: "not an OPL instrument bank".to_string(),
+            });
+        }
+        if bytes.len() < names_start + INSTRUMENT_COUNT * NAME_SIZE {
+            return Err(WadError::InvalidLump {
+                lump: lump.to_string(),
+                message: format!("too short to hold {} instruments", INSTRUMENT_COUNT),
+            });
+        }
+
+        let mut instruments = vec![];
+        for index in 0..INSTRUMENT_COUNT {
+            let buf = &bytes[8 + index * INSTRUMENT_SIZE..][..INSTRUMENT_SIZE];
+            let name_buf = &bytes[names_start + index * NAME_SIZE..][..NAME_SIZE];
+            instruments.push(OplInstrument {
+                name: buf_to_string(name_buf).map_err(|_| WadError::InvalidName {
+                    lump: lump.to_string(),
+                    index: Some(index),
+                })?,
+                flags: u16::from_le_bytes([buf[0], buf[1]]),
+                fine_tuning: buf[2],
+                fixed_note: buf[3],
+                voices: [decode_voice(&buf[4..20]), decode_voice(&buf[20..36])],
+            });
+        }
+
+        Ok(GenMidi { instruments })
+    }
+
+    // The other way round from `decode`, which is also a DMX OP2 bank file
+    pub fn encode(&self) -> Vec<u8> {
+        let mut buf = GENMIDI_SIGNATURE.to_vec();
+        for instrument in &self.instruments {
+            buf.extend_from_slice(&instrument.flags.to_le_bytes());
+            buf.push(instrument.fine_tuning);
+            buf.push(instrument.fixed_note);
+            for voice in &instrument.voices {
+                encode_voice(voice, &mut buf);
+            }
+        }
+        for instrument in &self.instruments {
+            let mut name = [0; NAME_SIZE];
+            let len = instrument.name.len().min(NAME_SIZE - 1);
+            name[..len].copy_from_slice(&instrument.name.as_bytes()[..len]);
+            buf.extend_from_slice(&name);
+        }
+        buf
+    }
+
+    // Percussion instruments are numbered by the MIDI note they play
+    pub fn percussion(&self, note: u8) -> Option<&OplInstrument> {
+        let index = note.checked_sub(GenMidi::FIRST_PERCUSSION_NOTE)? as usize;
+        self.instruments.get(128 + index)
+    }
+
+    // One instrument per line, so that two banks can be compared with a plain diff
+    pub fn to_json(&self) -> String {
+        let operator_json = |operator: &OplOperator| {
+            format!(
+                r#"{{"characteristic": {}, "attack_decay": {}, "sustain_release": {}, "waveform": {}, "key_scale": {}, "output_level": {}}}"#,
+                operator.characteristic,
+                operator.attack_decay,
+                operator.sustain_release,
+                operator.waveform,
+                operator.key_scale,
+                operator.output_level
+            )
+        };
+        let voice_json = |voice: &OplVoice| {
+            format!(
+                r#"{{"modulator": {}, "carrier": {}, "feedback": {}, "base_note_offset": {}}}"#,
+                operator_json(&voice.modulator),
+                operator_json(&voice.carrier),
+                voice.feedback,
+                voice.base_note_offset
+            )
+        };
+
+        let mut json = String::from("[\n");
+        for (index, instrument) in self.instruments.iter().enumerate() {
+            let _ = write!(
+                json,
+                r#"  {{"index": {}, "name": {}, "fixed_pitch": {}, "double_voice": {}, "fine_tuning": {}, "fixed_note": {}, "voices": [{}, {}]}}"#,
+                index,
+                json_string(&instrument.name),
+                instrument.is_fixed_pitch(),
+                instrument.is_double_voice(),
+                instrument.fine_tuning,
+                instrument.fixed_note,
+                voice_json(&instrument.voices[0]),
+                voice_json(&instrument.voices[1])
+            );
+            json.push_str(if index + 1 < self.instruments.len() {
+                ",\n"
+            } else {
+                "\n"
+            });
+        }
+        json.push_str("]\n");
+        json
+    }
+}
+
+impl Wad {
+    pub fn genmidi(&self) -> Result<GenMidi, WadError> {
+        let entry = self.find_lump("GENMIDI").ok_or(WadError::MissingLump {
+            lump: "GENMIDI".to_string(),
+        })?;
+        GenMidi::decode(&entry.name, &self.lump_bytes(entry)?)
+    }
+}
+
+impl WadStack {
+    pub fn genmidi(&self) -> Result<GenMidi, WadError> {
+        let lump = self.find_lump("GENMIDI").ok_or(WadError::MissingLump {
+            lump: "GENMIDI".to_string(),
+        })?;
+        GenMidi::decode(lump.name(), &lump.bytes()?)
+    }
+}
+
+fn decode_operator(buf: &[u8]) -> OplOperator {
+    OplOperator {
+        characteristic: buf[0],
+        attack_decay: buf[1],
+        sustain_release: buf[2],
+        waveform: buf[3],
+        key_scale: buf[4],
+        output_level: buf[5],
+    }
+}
+
+fn decode_voice(buf: &[u8]) -> OplVoice {
+    // The modulator, feedback, carrier, an unused byte and the note offset
+    OplVoice {
+        modulator: decode_operator(&buf[0..6]),
+        feedback: buf[6],
+        carrier: decode_operator(&buf[7..13]),
+        base_note_offset: i16::from_le_bytes([buf[14], buf[15]]),
+    }
+}
+
+fn encode_operator(operator: &OplOperator, buf: &mut Vec<u8>) {
+    buf.extend_from_slice(&[
+        operator.characteristic,
+        operator.attack_decay,
+        operator.sustain_release,
+        operator.waveform,
+        operator.key_scale,
+        operator.output_level,
+    ]);
+}
+
+fn encode_voice(voice: &OplVoice, buf: &mut Vec<u8>) {
+    encode_operator(&voice.modulator, buf);
+    buf.push(voice.feedback);
+    encode_operator(&voice.carrier, buf);
+    buf.push(0);
+    buf.extend_from_slice(&voice.base_note_offset.to_le_bytes());
+}
+
+fn json_string(value: &str) -> String {
+    let mut json = String::from("\"");
+    for c in value.chars() {
+        match c {
+            '"' => json.push_str("\\\""),
+            '\\' => json.push_str("\\\\"),
+            c if c.is_control() => {
+                let _ = write!(json, "\\u{:04x}", c as u32);
+            }
+            c => json.push(c),
+        }
+    }
+    json.push('"');
+    json
+}
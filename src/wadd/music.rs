@@ -0,0 +1,262 @@
+// Doom's music (the D_* lumps) is in MUS, a simplified form of MIDI made for the DMX sound
+// library. See https://doomwiki.org/wiki/MUS
+
+use super::WadError;
+
+#[derive(Clone, Debug)]
+pub struct Mus {
+    pub primary_channels: u16,
+    pub secondary_channels: u16,
+    // The General MIDI patches the song uses, so they can be loaded ahead of time
+    pub instruments: Vec<u16>,
+    pub events: Vec<MusEvent>,
+}
+
+#[derive(Clone, Debug)]
+pub struct MusEvent {
+    // 0 to 15; channel 15 is percussion
+    pub channel: u8,
+    pub kind: MusEventKind,
+    // How long to wait after this event before the next one, in 140 Hz tics
+    pub delay: u32,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum MusEventKind {
+    ReleaseNote { note: u8 },
+    // Without a velocity, the channel's previous one is used
+    PlayNote { note: u8, velocity: Option<u8> },
+    // 128 is centered, and 0 and 255 are a full tone down and up
+    PitchBend { bend: u8 },
+    // 10 to 14: all sounds off, all notes off, mono, poly and reset all controllers
+    System { controller: u8 },
+    // Controller 0 changes the instrument; 1 to 9 are bank select, modulation, volume, pan,
+    // expression, reverb, chorus, sustain and soft pedal
+    Controller { controller: u8, value: u8 },
+    EndOfMeasure,
+    ScoreEnd,
+}
+
+// MIDI controller numbers for MUS controllers 1 to 9 (0 is a program change instead)
+const MIDI_CONTROLLERS: [u8; 10] = [0, 0, 1, 7, 10, 11, 91, 93, 64, 67];
+// MIDI controller numbers for MUS system events 10 to 14
+const MIDI_SYSTEM_CONTROLLERS: [u8; 5] = [120, 123, 126, 127, 121];
+
+// MUS and MIDI disagree on which channel is percussion
+const MUS_PERCUSSION_CHANNEL: u8 = 15;
+const MIDI_PERCUSSION_CHANNEL: u8 = 9;
+
+impl Mus {
+    pub fn decode(lump: &str, bytes: &[u8]) -> Result<Mus, WadError> {
+        let invalid = |message: &str| WadError::InvalidLump {
+            lump: lump.to_string(),
+            message: message.to_string(),
+        };
+        let read_u16 = |offset: usize| {
+            bytes
+                .get(offset..offset + 2)
+                .map(|buf| u16::from_le_bytes([buf[0], buf[1]]))
+                .ok_or_else(|| invalid("MUS header runs past the end of the lump"))
+        };
+
+        // A 16 byte header, followed by the list of instruments
+        if !bytes.starts_with(b"MUS\x1A") {
+            return Err(invalid("not a MUS lump"));
+        }
+        let score_length = read_u16(4)? as usize;
+        let score_start = read_u16(6)? as usize;
+        let primary_channels = read_u16(8)?;
+        let secondary_channels = read_u16(10)?;
+        let instrument_count = read_u16(12)? as usize;
+        let instruments = (0..instrument_count)
+            .map(|i| read_u16(16 + i * 2))
+            .collect::<Result<Vec<u16>, WadError>>()?;
+
+        let score = bytes
+            .get(score_start..)
+            .ok_or_else(|| invalid("MUS score starts past the end of the lump"))?;
+        // Some MUS lumps have the wrong score length, which the game ignores, so only use it if
+        // it's shorter than what's there
+        let score = &score[..score_length.min(score.len())];
+
+        let mut events = vec![];
+        let mut pos = 0;
+        let mut next_byte = || {
+            let byte = score.get(pos).copied();
+            pos += 1;
+            byte.ok_or_else(|| invalid("MUS score ends in the middle of an event"))
+        };
+        loop {
+            let descriptor = next_byte()?;
+            let channel = descriptor & 0x0F;
+            let kind = match (descriptor >> 4) & 0x07 {
+                0 => MusEventKind::ReleaseNote {
+                    note: next_byte()? & 0x7F,
+                },
+                1 => {
+                    let note = next_byte()?;
+                    let velocity = match note & 0x80 {
+                        0 => None,
+                        _ => Some(next_byte()? & 0x7F),
+                    };
+                    MusEventKind::PlayNote {
+                        note: note & 0x7F,
+                        velocity,
+                    }
+                }
+                2 => MusEventKind::PitchBend { bend: next_byte()? },
+                3 => MusEventKind::System {
+                    controller: next_byte()?,
+                },
+                4 => MusEventKind::Controller {
+                    controller: next_byte()?,
+                    value: next_byte()?,
+                },
+                5 => MusEventKind::EndOfMeasure,
+                6 => MusEventKind::ScoreEnd,
+                _ => return Err(invalid("MUS score has an unknown event type")),
+            };
+
+            // The delay is a variable length number, 7 bits at a time, most significant first.
+            // Nothing comes after the end of the score, so its delay doesn't matter.
+            let mut delay: u32 = 0;
+            if descriptor & 0x80 != 0 && kind != MusEventKind::ScoreEnd {
+                loop {
+                    let byte = next_byte()?;
+                    delay = delay.saturating_mul(128) | (byte & 0x7F) as u32;
+                    if byte & 0x80 == 0 {
+                        break;
+                    }
+                }
+            }
+
+            let is_end = kind == MusEventKind::ScoreEnd;
+            events.push(MusEvent {
+                channel,
+                kind,
+                delay,
+            });
+            if is_end {
+                break;
+            }
+        }
+
+        Ok(Mus {
+            primary_channels,
+            secondary_channels,
+            instruments,
+            events,
+        })
+    }
+
+    // A type 0 Standard MIDI File. The timing is set up so that one MIDI tick is one MUS tic.
+    pub fn to_midi(&self) -> Vec<u8> {
+        // 70 ticks per quarter note at the default 120 BPM gives 140 ticks per second
+        const TICKS_PER_QUARTER_NOTE: u16 = 70;
+        const MICROSECONDS_PER_QUARTER_NOTE: u32 = 500_000;
+
+        let mut track = MidiTrack::default();
+        let tempo = MICROSECONDS_PER_QUARTER_NOTE.to_be_bytes();
+        track.event(&[0xFF, 0x51, 0x03, tempo[1], tempo[2], tempo[3]]);
+
+        let mut velocities = [127u8; 16];
+        let mut channels_used = [false; 16];
+        for event in &self.events {
+            let channel = match event.channel {
+                MUS_PERCUSSION_CHANNEL => MIDI_PERCUSSION_CHANNEL,
+                MIDI_PERCUSSION_CHANNEL => MUS_PERCUSSION_CHANNEL,
+                channel => channel,
+            };
+
+            // MUS channels start at full volume, but MIDI ones don't
+            if !channels_used[channel as usize] {
+                channels_used[channel as usize] = true;
+                track.event(&[0xB0 | channel, 7, 127]);
+            }
+
+            match event.kind {
+                MusEventKind::ReleaseNote { note } => track.event(&[0x80 | channel, note, 64]),
+                MusEventKind::PlayNote { note, velocity } => {
+                    let velocity = velocity.unwrap_or(velocities[event.channel as usize]);
+                    velocities[event.channel as usize] = velocity;
+                    track.event(&[0x90 | channel, note, velocity])
+                }
+                MusEventKind::PitchBend { bend } => {
+                    let bend = bend as u16 * 64;
+                    track.event(&[0xE0 | channel, (bend & 0x7F) as u8, (bend >> 7) as u8])
+                }
+                MusEventKind::System { controller } => {
+                    let midi_controller = (controller as usize)
+                        .checked_sub(10)
+                        .and_then(|index| MIDI_SYSTEM_CONTROLLERS.get(index));
+                    if let Some(&midi_controller) = midi_controller {
+                        track.event(&[0xB0 | channel, midi_controller, 0])
+                    }
+                }
+                // Some songs have values over 127, which the game clamps
+                MusEventKind::Controller {
+                    controller: 0,
+                    value,
+                } => track.event(&[0xC0 | channel, value.min(127)]),
+                MusEventKind::Controller { controller, value } => {
+                    if let Some(&midi_controller) = MIDI_CONTROLLERS.get(controller as usize) {
+                        track.event(&[0xB0 | channel, midi_controller, value.min(127)])
+                    }
+                }
+                MusEventKind::EndOfMeasure | MusEventKind::ScoreEnd => {}
+            }
+            track.wait(event.delay);
+        }
+        track.event(&[0xFF, 0x2F, 0x00]);
+
+        let mut midi = vec![];
+        midi.extend_from_slice(b"MThd");
+        midi.extend_from_slice(&6u32.to_be_bytes());
+        midi.extend_from_slice(&0u16.to_be_bytes()); // Format 0: a single track
+        midi.extend_from_slice(&1u16.to_be_bytes());
+        midi.extend_from_slice(&TICKS_PER_QUARTER_NOTE.to_be_bytes());
+        midi.extend_from_slice(b"MTrk");
+        midi.extend_from_slice(&(track.buf.len() as u32).to_be_bytes());
+        midi.extend_from_slice(&track.buf);
+        midi
+    }
+}
+
+// Music lumps can be MUS or, in many PWADs, MIDI already. MIDI passes through unchanged.
+pub fn music_to_midi(lump: &str, bytes: &[u8]) -> Result<Vec<u8>, WadError> {
+    if bytes.starts_with(b"MThd") {
+        return Ok(bytes.to_vec());
+    }
+    Ok(Mus::decode(lump, bytes)?.to_midi())
+}
+
+#[derive(Default)]
+struct MidiTrack {
+    buf: Vec<u8>,
+    // Time since the last event, in ticks
+    delta: u32,
+}
+
+impl MidiTrack {
+    fn event(&mut self, event: &[u8]) {
+        write_variable_length(&mut self.buf, self.delta);
+        self.buf.extend_from_slice(event);
+        self.delta = 0;
+    }
+
+    fn wait(&mut self, ticks: u32) {
+        self.delta = self.delta.saturating_add(ticks);
+    }
+}
+
+// MIDI's variable length numbers are 7 bits at a time, most significant first, with the top bit
+// set on every byte but the last
+fn write_variable_length(buf: &mut Vec<u8>, value: u32) {
+    let mut bytes = vec![(value & 0x7F) as u8];
+    let mut value = value >> 7;
+    while value > 0 {
+        bytes.push((value & 0x7F) as u8 | 0x80);
+        value >>= 7;
+    }
+    buf.extend(bytes.iter().rev());
+}
@@ -0,0 +1,433 @@
+// Maps don't store sectors as shapes, only as the linedefs whose sidedefs face into them. Turning
+// those back into polygons (for drawing, or measuring area) means walking the lines into closed
+// loops, then working out which loops are holes in which others.
+
+use std::collections::HashMap;
+use std::f64::consts::PI;
+use std::fmt;
+
+use super::{MapData, Vertex};
+
+// A polygon's rings all wind the same way in map coordinates (where y points up): outer rings
+// counterclockwise and holes clockwise. The first vertex isn't repeated at the end.
+#[derive(Clone, Debug)]
+pub struct SectorPolygon {
+    pub outer: Vec<Vertex>,
+    // Areas inside the outer ring that belong to other sectors (or to no sector at all)
+    pub holes: Vec<Vec<Vertex>>,
+}
+
+#[derive(Clone, Debug)]
+pub struct SectorPolygons {
+    // A sector can be made up of any number of separate areas
+    pub polygons: Vec<SectorPolygon>,
+    pub diagnostics: Vec<SectorDiagnostic>,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum SectorDiagnostic {
+    // These lines don't join up into closed loops, so they're left out of the polygons. The game
+    // copes with this, but the sector's shape is ambiguous.
+    Unclosed { linedefs: Vec<usize> },
+    // The loops made by these lines cross each other, so which areas are holes is a guess
+    Crossing { linedefs: Vec<usize> },
+}
+
+impl fmt::Display for SectorDiagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let list = |linedefs: &[usize]| {
+            let linedefs: Vec<String> = linedefs.iter().map(|l| l.to_string()).collect();
+            linedefs.join(", ")
+        };
+        match self {
+            SectorDiagnostic::Unclosed { linedefs } => {
+                write!(f, "sector isn't closed (linedefs {})", list(linedefs))
+            }
+            SectorDiagnostic::Crossing { linedefs } => {
+                write!(
+                    f,
+                    "sector's lines cross each other (linedefs {})",
+                    list(linedefs)
+                )
+            }
+        }
+    }
+}
+
+// A sector's side of a linedef, pointing so that the sector is on its right
+#[derive(Clone, Copy)]
+struct Edge {
+    from: usize,
+    to: usize,
+    linedef: usize,
+}
+
+// A closed loop of a sector's boundary
+struct Ring<P> {
+    points: Vec<P>,
+    linedefs: Vec<usize>,
+}
+
+impl MapData {
+    pub fn sector_polygons(&self, sector: usize) -> SectorPolygons {
+        // Vertexes are compared by position, since some maps have duplicates at the same spot
+        let mut points: Vec<Vertex> = vec![];
+        let mut point_ids: HashMap<(i16, i16), usize> = HashMap::new();
+        let mut point_id = |vertex: Vertex| {
+            *point_ids.entry((vertex.x, vertex.y)).or_insert_with(|| {
+                points.push(vertex);
+                points.len() - 1
+            })
+        };
+
+        let side_sector = |sidedef: i16| {
+            usize::try_from(sidedef)
+                .ok()
+                .and_then(|sidedef| self.sidedefs.get(sidedef))
+                .map(|sidedef| sidedef.sector as usize)
+        };
+        let mut edges: Vec<Edge> = vec![];
+        for (index, linedef) in self.linedefs.iter().enumerate() {
+            let vertex = |v: i16| self.vertexes.get(usize::try_from(v).ok()?).copied();
+            let (Some(begin), Some(end)) =
+                (vertex(linedef.vertex_begin), vertex(linedef.vertex_end))
+            else {
+                continue;
+            };
+            let (begin, end) = (point_id(begin), point_id(end));
+            let right = side_sector(linedef.sidedef_right) == Some(sector);
+            let left = side_sector(linedef.sidedef_left) == Some(sector);
+            // Lines with the sector on both sides are inside it, not on its boundary
+            if begin == end || right == left {
+                continue;
+            }
+            edges.push(match right {
+                true => Edge {
+                    from: begin,
+                    to: end,
+                    linedef: index,
+                },
+                false => Edge {
+                    from: end,
+                    to: begin,
+                    linedef: index,
+                },
+            });
+        }
+
+        let (rings, unclosed) = trace_rings(&points, &edges);
+        let rings: Vec<Ring<Vertex>> = rings
+            .into_iter()
+            .map(|ring| Ring {
+                points: ring.points.iter().map(|&point| points[point]).collect(),
+                linedefs: ring.linedefs,
+            })
+            .collect();
+        let (polygons, crossing) = nest_rings(&rings);
+
+        let mut diagnostics = vec![];
+        let unclosed = sorted_linedefs(unclosed);
+        if !unclosed.is_empty() {
+            diagnostics.push(SectorDiagnostic::Unclosed { linedefs: unclosed });
+        }
+        let crossing = sorted_linedefs(crossing);
+        if !crossing.is_empty() {
+            diagnostics.push(SectorDiagnostic::Crossing { linedefs: crossing });
+        }
+
+        SectorPolygons {
+            polygons,
+            diagnostics,
+        }
+    }
+}
+
+fn sorted_linedefs(mut linedefs: Vec<usize>) -> Vec<usize> {
+    linedefs.sort_unstable();
+    linedefs.dedup();
+    linedefs
+}
+
+// Joins edges end to end into closed rings of point ids, returning the rings and the linedefs of
+// any edges that couldn't be closed
+fn trace_rings(points: &[Vertex], edges: &[Edge]) -> (Vec<Ring<usize>>, Vec<usize>) {
+    let mut outgoing: HashMap<usize, Vec<usize>> = HashMap::new();
+    for (index, edge) in edges.iter().enumerate() {
+        outgoing.entry(edge.from).or_default().push(index);
+    }
+    let angle = |from: usize, to: usize| {
+        let (from, to) = (points[from], points[to]);
+        (to.y as f64 - from.y as f64).atan2(to.x as f64 - from.x as f64)
+    };
+
+    let mut used = vec![false; edges.len()];
+    let mut rings = vec![];
+    let mut unclosed = vec![];
+    for start in 0..edges.len() {
+        if used[start] {
+            continue;
+        }
+        used[start] = true;
+        // The points visited so far, and the edges between them
+        let mut path = vec![edges[start].from, edges[start].to];
+        let mut path_edges = vec![start];
+
+        loop {
+            let &current = path.last().unwrap();
+            let previous = path[path.len() - 2];
+
+            // Coming back to a point already on the path closes a ring. Splitting it off there
+            // (rather than only closing at the start) keeps rings that touch at a vertex apart.
+            if let Some(position) = path[..path.len() - 1].iter().position(|&p| p == current) {
+                rings.push(Ring {
+                    points: path[position..path.len() - 1].to_vec(),
+                    linedefs: path_edges[position..]
+                        .iter()
+                        .map(|&edge| edges[edge].linedef)
+                        .collect(),
+                });
+                path.truncate(position + 1);
+                path_edges.truncate(position);
+                if path_edges.is_empty() {
+                    break;
+                }
+                continue;
+            }
+
+            // Where several lines leave the same point, take the sharpest right turn, which keeps
+            // to the edge of the sector (it's on the right) instead of cutting across it
+            let back = angle(current, previous);
+            let next = outgoing
+                .get(&current)
+                .into_iter()
+                .flatten()
+                .copied()
+                .filter(|&edge| !used[edge])
+                .min_by(|&a, &b| {
+                    let turn = |edge: usize| {
+                        let turn = (angle(current, edges[edge].to) - back).rem_euclid(2.0 * PI);
+                        // Doubling straight back is the last resort
+                        if turn == 0.0 {
+                            2.0 * PI
+                        } else {
+                            turn
+                        }
+                    };
+                    turn(a).total_cmp(&turn(b))
+                });
+
+            match next {
+                Some(edge) => {
+                    used[edge] = true;
+                    path.push(edges[edge].to);
+                    path_edges.push(edge);
+                }
+                None => {
+                    // A dead end: whatever is left of the path can't be closed
+                    unclosed.extend(path_edges.iter().map(|&edge| edges[edge].linedef));
+                    break;
+                }
+            }
+        }
+    }
+
+    (rings, unclosed)
+}
+
+// Sorts rings into polygons by how deeply they're nested inside each other: a ring inside an even
+// number of the sector's other rings is an outer ring, and one inside an odd number is a hole in
+// the smallest outer ring around it. This doesn't rely on the sidedefs facing the right way.
+// Rings that cross each other can throw the count off, so also returns the linedefs of any hole
+// that had to be placed by guesswork.
+fn nest_rings(rings: &[Ring<Vertex>]) -> (Vec<SectorPolygon>, Vec<usize>) {
+    let areas: Vec<f64> = rings.iter().map(|ring| signed_area(&ring.points)).collect();
+    // Which rings each ring is inside, smallest first
+    let parents: Vec<Vec<usize>> = (0..rings.len())
+        .map(|inner| {
+            let mut parents: Vec<usize> = (0..rings.len())
+                .filter(|&outer| {
+                    outer != inner
+                        && areas[outer].abs() > areas[inner].abs()
+                        && ring_inside(&rings[inner].points, &rings[outer].points)
+                })
+                .collect();
+            parents.sort_by(|&a, &b| areas[a].abs().total_cmp(&areas[b].abs()));
+            parents
+        })
+        .collect();
+    let is_outer = |index: usize| parents[index].len().is_multiple_of(2);
+
+    let mut polygons = vec![];
+    let mut polygon_for_ring = HashMap::new();
+    for (index, ring) in rings.iter().enumerate() {
+        if is_outer(index) {
+            polygon_for_ring.insert(index, polygons.len());
+            polygons.push(SectorPolygon {
+                outer: wound(&ring.points, areas[index], true),
+                holes: vec![],
+            });
+        }
+    }
+
+    let mut crossing = vec![];
+    for (index, ring) in rings.iter().enumerate() {
+        if is_outer(index) {
+            continue;
+        }
+        // Rings that don't cross always have an outer ring as their smallest parent; otherwise the
+        // nearest outer ring around the hole is the best guess
+        let parent = parents[index].iter().find(|&&parent| is_outer(parent));
+        if parent != parents[index].first() {
+            crossing.extend(&ring.linedefs);
+        }
+        match parent.and_then(|parent| polygon_for_ring.get(parent)) {
+            Some(&polygon) => {
+                polygons[polygon]
+                    .holes
+                    .push(wound(&ring.points, areas[index], false))
+            }
+            // Nothing around it is an outer ring, so it's drawn as one rather than being lost
+            None => polygons.push(SectorPolygon {
+                outer: wound(&ring.points, areas[index], true),
+                holes: vec![],
+            }),
+        }
+    }
+    (polygons, crossing)
+}
+
+// Positive for counterclockwise rings
+fn signed_area(ring: &[Vertex]) -> f64 {
+    let mut area = 0.0;
+    for (i, a) in ring.iter().enumerate() {
+        let b = ring[(i + 1) % ring.len()];
+        area += a.x as f64 * b.y as f64 - b.x as f64 * a.y as f64;
+    }
+    area / 2.0
+}
+
+fn wound(ring: &[Vertex], area: f64, counterclockwise: bool) -> Vec<Vertex> {
+    let mut ring = ring.to_vec();
+    if (area > 0.0) != counterclockwise {
+        ring.reverse();
+    }
+    ring
+}
+
+// Rings from the same sector can share vertexes, but in a well-formed map they don't cross, so the
+// first vertex of `inner` that isn't on `outer`'s boundary says which side of it the whole ring is
+// on
+fn ring_inside(inner: &[Vertex], outer: &[Vertex]) -> bool {
+    inner
+        .iter()
+        .find_map(|&point| point_in_ring(point, outer))
+        .unwrap_or(false)
+}
+
+// None if the point is on the ring's boundary
+fn point_in_ring(point: Vertex, ring: &[Vertex]) -> Option<bool> {
+    let (px, py) = (point.x as f64, point.y as f64);
+    let mut inside = false;
+    for (i, a) in ring.iter().enumerate() {
+        let b = ring[(i + 1) % ring.len()];
+        let (ax, ay, bx, by) = (a.x as f64, a.y as f64, b.x as f64, b.y as f64);
+
+        let cross = (bx - ax) * (py - ay) - (by - ay) * (px - ax);
+        if cross == 0.0
+            && px >= ax.min(bx)
+            && px <= ax.max(bx)
+            && py >= ay.min(by)
+            && py <= ay.max(by)
+        {
+            return None;
+        }
+        if (ay > py) != (by > py) && px < ax + (py - ay) * (bx - ax) / (by - ay) {
+            inside = !inside;
+        }
+    }
+    Some(inside)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::wadd::{LineDef, MapFormat, RejectTable, Sector, SideDef};
+
+    // A single sector bounded by the given rectangles, each facing into it
+    fn map_with_rectangles(rectangles: &[(i16, i16, i16, i16)]) -> MapData {
+        let mut vertexes = vec![];
+        let mut linedefs = vec![];
+        for &(x1, y1, x2, y2) in rectangles {
+            let first = vertexes.len() as i16;
+            vertexes.extend([(x1, y1), (x1, y2), (x2, y2), (x2, y1)].map(|(x, y)| Vertex { x, y }));
+            for i in 0..4 {
+                linedefs.push(LineDef {
+                    vertex_begin: first + i,
+                    vertex_end: first + (i + 1) % 4,
+                    flags: 0,
+                    line_type: 0,
+                    sector_tag: 0,
+                    sidedef_right: 0,
+                    sidedef_left: -1,
+                });
+            }
+        }
+
+        MapData {
+            name: "MAP01".to_string(),
+            format: MapFormat::Doom,
+            linedefs,
+            sectors: vec![Sector {
+                floor_height: 0,
+                ceiling_height: 128,
+                floor_texture: "FLAT".into(),
+                ceiling_texture: "CEIL".into(),
+                light_level: 160,
+                special: 0,
+                sector_tag: 0,
+            }],
+            sidedefs: vec![SideDef {
+                x: 0,
+                y: 0,
+                upper_texture: None,
+                lower_texture: None,
+                middle_texture: None,
+                sector: 0,
+            }],
+            things: vec![],
+            vertexes,
+            nodes: vec![],
+            segs: vec![],
+            subsectors: vec![],
+            blockmap: None,
+            reject: RejectTable::missing(1),
+        }
+    }
+
+    #[test]
+    fn hole_inside_outer_ring() {
+        let map = map_with_rectangles(&[(0, 0, 100, 100), (10, 10, 20, 20)]);
+        let polygons = map.sector_polygons(0);
+        assert_eq!(polygons.polygons.len(), 1);
+        assert_eq!(polygons.polygons[0].holes.len(), 1);
+        assert!(polygons.diagnostics.is_empty());
+    }
+
+    #[test]
+    fn crossing_rings_are_reported() {
+        let map = map_with_rectangles(&[(0, 0, 100, 100), (10, 80, 20, 90), (-50, -50, 300, 50)]);
+        let polygons = map.sector_polygons(0);
+        let rings: usize = polygons
+            .polygons
+            .iter()
+            .map(|polygon| 1 + polygon.holes.len())
+            .sum();
+        assert_eq!(rings, 3);
+        assert_eq!(
+            polygons.diagnostics,
+            vec![SectorDiagnostic::Crossing {
+                linedefs: vec![4, 5, 6, 7]
+            }]
+        );
+    }
+}
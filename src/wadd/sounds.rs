@@ -0,0 +1,200 @@
+// Doom's sound effects (the DS* lumps) are stored in the format used by the DMX sound library. See
+// https://doomwiki.org/wiki/Sound
+
+use super::WadError;
+
+#[derive(Clone, Debug)]
+pub struct Sound {
+    pub sample_rate: u16,
+    // Unsigned 8-bit mono PCM, without the padding DMX skips over
+    pub samples: Vec<u8>,
+}
+
+impl Sound {
+    pub fn decode(lump: &str, bytes: &[u8]) -> Result<Sound, WadError> {
+        let invalid = |message: &str| WadError::InvalidLump {
+            lump: lump.to_string(),
+            message: message.to_string(),
+        };
+
+        // An 8 byte header: the format number (always 3), the sample rate and the number of samples
+        if bytes.len() < 8 {
+            return Err(invalid("too short to be a DMX sound"));
+        }
+        let format = u16::from_le_bytes([bytes[0], bytes[1]]);
+        let sample_rate = u16::from_le_bytes([bytes[2], bytes[3]]);
+        let sample_count = u32::from_le_bytes(bytes[4..8].try_into().unwrap()) as usize;
+        if format != 3 {
+            return Err(invalid("not a DMX sound"));
+        }
+        if sample_rate == 0 {
+            return Err(invalid("DMX sound has a sample rate of 0"));
+        }
+
+        // The sample count includes 16 bytes of padding at either end, which DMX doesn't play
+        const PADDING: usize = 16;
+        let samples = bytes
+            .get(8..8 + sample_count)
+            .ok_or_else(|| invalid("DMX sound is shorter than its header says"))?;
+        let samples = samples
+            .get(PADDING..sample_count.saturating_sub(PADDING))
+            .unwrap_or_default();
+
+        Ok(Sound {
+            sample_rate,
+            samples: samples.to_vec(),
+        })
+    }
+
+    pub fn duration_seconds(&self) -> f64 {
+        self.samples.len() as f64 / self.sample_rate as f64
+    }
+
+    // A standard RIFF WAVE file with the same unsigned 8-bit mono samples
+    pub fn to_wav(&self) -> Vec<u8> {
+        encode_wav(self.sample_rate as u32, &self.samples)
+    }
+}
+
+// Unsigned 8-bit mono PCM, which both DMX sounds and synthesized PC speaker sounds use
+fn encode_wav(sample_rate: u32, samples: &[u8]) -> Vec<u8> {
+    // Chunks are padded to an even length
+    let padding = samples.len() % 2;
+    let mut buf = Vec::with_capacity(44 + samples.len() + padding);
+    buf.extend_from_slice(b"RIFF");
+    buf.extend_from_slice(&((36 + samples.len() + padding) as u32).to_le_bytes());
+    buf.extend_from_slice(b"WAVE");
+
+    buf.extend_from_slice(b"fmt ");
+    buf.extend_from_slice(&16u32.to_le_bytes());
+    buf.extend_from_slice(&1u16.to_le_bytes()); // PCM
+    buf.extend_from_slice(&1u16.to_le_bytes()); // Mono
+    buf.extend_from_slice(&sample_rate.to_le_bytes());
+    buf.extend_from_slice(&sample_rate.to_le_bytes()); // Bytes per second
+    buf.extend_from_slice(&1u16.to_le_bytes()); // Bytes per sample
+    buf.extend_from_slice(&8u16.to_le_bytes()); // Bits per sample
+
+    buf.extend_from_slice(b"data");
+    buf.extend_from_slice(&(samples.len() as u32).to_le_bytes());
+    buf.extend_from_slice(samples);
+    buf.resize(buf.len() + padding, 0);
+    buf
+}
+
+// The DP* lumps are the sound effects played on the PC speaker: a list of tones, each played for
+// one tic of the 140 Hz timer. See https://doomwiki.org/wiki/PC_speaker_sound_effects
+#[derive(Clone, Debug)]
+pub struct PcSpeakerSound {
+    // Consecutive tics playing the same tone are combined
+    pub tones: Vec<PcSpeakerTone>,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct PcSpeakerTone {
+    // 0 is silence; 1 to 127 are notes, roughly a quarter tone apart
+    pub tone: u8,
+    // In 140 Hz tics
+    pub duration: u32,
+}
+
+// The PC's timer runs at this rate, and the speaker's pitch is set by dividing it
+const PC_TIMER_FREQUENCY: f64 = 1_193_181.0;
+
+// The timer divisor for each tone, from the DMX library that Doom used
+const PC_SPEAKER_DIVISORS: [u16; 128] = [
+    0, 6818, 6628, 6449, 6279, 6087, 5906, 5736, 5575, 5423, 5279, 5120, 4971, 4830, 4697, 4554,
+    4435, 4307, 4186, 4058, 3950, 3836, 3728, 3615, 3519, 3418, 3323, 3224, 3131, 3043, 2960, 2875,
+    2794, 2711, 2633, 2560, 2485, 2415, 2348, 2281, 2213, 2153, 2089, 2032, 1975, 1918, 1864, 1810,
+    1757, 1709, 1659, 1612, 1565, 1521, 1478, 1435, 1395, 1355, 1316, 1280, 1242, 1207, 1173, 1140,
+    1107, 1075, 1045, 1015, 986, 959, 931, 905, 879, 854, 829, 806, 783, 760, 739, 718, 697, 677,
+    658, 640, 621, 604, 586, 570, 553, 538, 522, 507, 493, 479, 465, 452, 439, 427, 415, 403, 391,
+    380, 369, 359, 348, 339, 329, 319, 310, 302, 293, 285, 276, 269, 261, 253, 246, 239, 232, 226,
+    219, 213, 207, 201, 195, 190, 184, 179,
+];
+
+impl PcSpeakerTone {
+    // None for silence
+    pub fn frequency(&self) -> Option<f64> {
+        match self.tone {
+            0 => None,
+            tone => Some(PC_TIMER_FREQUENCY / PC_SPEAKER_DIVISORS[tone as usize] as f64),
+        }
+    }
+}
+
+impl PcSpeakerSound {
+    pub const TICS_PER_SECOND: u32 = 140;
+
+    pub fn decode(lump: &str, bytes: &[u8]) -> Result<PcSpeakerSound, WadError> {
+        let invalid = |message: &str| WadError::InvalidLump {
+            lump: lump.to_string(),
+            message: message.to_string(),
+        };
+
+        // A 4 byte header: the format number (always 0) and the number of tones
+        if bytes.len() < 4 {
+            return Err(invalid("too short to be a PC speaker sound"));
+        }
+        let format = u16::from_le_bytes([bytes[0], bytes[1]]);
+        let tone_count = u16::from_le_bytes([bytes[2], bytes[3]]) as usize;
+        if format != 0 {
+            return Err(invalid("not a PC speaker sound"));
+        }
+        let tone_buf = bytes
+            .get(4..4 + tone_count)
+            .ok_or_else(|| invalid("PC speaker sound is shorter than its header says"))?;
+
+        let mut tones: Vec<PcSpeakerTone> = vec![];
+        for &tone in tone_buf {
+            if tone as usize >= PC_SPEAKER_DIVISORS.len() {
+                return Err(invalid("PC speaker sound has a tone out of range"));
+            }
+            match tones.last_mut() {
+                Some(last) if last.tone == tone => last.duration += 1,
+                _ => tones.push(PcSpeakerTone { tone, duration: 1 }),
+            }
+        }
+
+        Ok(PcSpeakerSound { tones })
+    }
+
+    pub fn duration_seconds(&self) -> f64 {
+        let tics: u32 = self.tones.iter().map(|tone| tone.duration).sum();
+        tics as f64 / PcSpeakerSound::TICS_PER_SECOND as f64
+    }
+
+    // Plays the tones as a square wave, the way the speaker would, as unsigned 8-bit mono PCM
+    pub fn synthesize(&self, sample_rate: u32) -> Vec<u8> {
+        const VOLUME: f64 = 48.0;
+
+        let mut samples = vec![];
+        // The phase carries on from one tone into the next, like the speaker's timer
+        let mut phase = 0.0;
+        let mut tics = 0;
+        for tone in &self.tones {
+            let start = tics * sample_rate as u64 / PcSpeakerSound::TICS_PER_SECOND as u64;
+            tics += tone.duration as u64;
+            let end = tics * sample_rate as u64 / PcSpeakerSound::TICS_PER_SECOND as u64;
+
+            for _ in start..end {
+                let sample = match tone.frequency() {
+                    None => 0.0,
+                    Some(frequency) => {
+                        phase = (phase + frequency / sample_rate as f64).fract();
+                        if phase < 0.5 {
+                            VOLUME
+                        } else {
+                            -VOLUME
+                        }
+                    }
+                };
+                samples.push((128.0 + sample) as u8);
+            }
+        }
+        samples
+    }
+
+    pub fn to_wav(&self, sample_rate: u32) -> Vec<u8> {
+        encode_wav(sample_rate, &self.synthesize(sample_rate))
+    }
+}
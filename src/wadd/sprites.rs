@@ -0,0 +1,396 @@
+// Sprites live between S_START and S_END. Each lump is named after the sprite (4 characters), the
+// animation frame (a letter) and the rotation it's seen from (0 for all angles, or 1 to 8 going
+// anticlockwise from the front). A lump can be used for a second frame and rotation as well, drawn
+// mirrored, by adding them to the end of its name: TROOA2A8 is the imp's frame A seen from
+// rotation 2, and mirrored for rotation 8. See https://doomwiki.org/wiki/Sprite
+
+use std::collections::BTreeMap;
+
+use super::{MapData, MapFormat, Picture, Wad, WadError, WadStack};
+
+#[derive(Clone, Debug)]
+pub struct SpriteRotation {
+    // 0 if this lump is used for every angle
+    pub rotation: u8,
+    pub lump: String,
+    pub mirrored: bool,
+}
+
+#[derive(Clone, Debug)]
+pub struct SpriteFrame {
+    pub frame: char,
+    // Sorted by rotation
+    pub rotations: Vec<SpriteRotation>,
+}
+
+#[derive(Clone, Debug)]
+pub struct Sprite {
+    pub name: String,
+    // Sorted by frame
+    pub frames: Vec<SpriteFrame>,
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct SpriteSet {
+    // Sorted by name
+    pub sprites: Vec<Sprite>,
+    // Lumps in the sprite namespace whose names don't follow the naming scheme
+    pub invalid_lumps: Vec<String>,
+}
+
+// A thing in a map whose sprite isn't in the SpriteSet
+#[derive(Clone, Debug)]
+pub struct MissingSprite {
+    // Index into MapData::things
+    pub thing: usize,
+    pub thing_type: i16,
+    pub sprite: &'static str,
+}
+
+impl SpriteSet {
+    // Builds the set from the names of the lumps in the sprite namespace, in directory order. If
+    // the same frame and rotation turns up more than once, the last lump wins.
+    pub fn new<'a>(lump_names: impl IntoIterator<Item = &'a str>) -> SpriteSet {
+        type Frames = BTreeMap<char, BTreeMap<u8, SpriteRotation>>;
+        let mut sprites: BTreeMap<String, Frames> = BTreeMap::new();
+        let mut invalid_lumps = vec![];
+
+        for lump_name in lump_names {
+            let name = lump_name.to_ascii_uppercase();
+            let Some(views) = parse_sprite_lump_name(&name) else {
+                invalid_lumps.push(lump_name.to_string());
+                continue;
+            };
+
+            let frames = sprites.entry(name[..4].to_string()).or_default();
+            for (frame, rotation, mirrored) in views {
+                frames.entry(frame).or_default().insert(
+                    rotation,
+                    SpriteRotation {
+                        rotation,
+                        lump: lump_name.to_string(),
+                        mirrored,
+                    },
+                );
+            }
+        }
+
+        SpriteSet {
+            sprites: sprites
+                .into_iter()
+                .map(|(name, frames)| Sprite {
+                    name,
+                    frames: frames
+                        .into_iter()
+                        .map(|(frame, rotations)| SpriteFrame {
+                            frame,
+                            rotations: rotations.into_values().collect(),
+                        })
+                        .collect(),
+                })
+                .collect(),
+            invalid_lumps,
+        }
+    }
+
+    pub fn sprite(&self, name: &str) -> Option<&Sprite> {
+        self.sprites
+            .iter()
+            .find(|sprite| sprite.name.eq_ignore_ascii_case(name))
+    }
+
+    // Things whose sprite isn't present. Only the Doom and Doom II thing types are known, so
+    // Hexen-format maps and UDMF maps in other namespaces (which number their things differently)
+    // are skipped, as are things with types that aren't recognized.
+    pub fn missing_sprites(&self, map: &MapData) -> Vec<MissingSprite> {
+        let uses_doom_thing_types = match &map.format {
+            MapFormat::Doom => true,
+            MapFormat::Hexen { .. } => false,
+            MapFormat::Udmf(udmf) => ["doom", "zdoomtranslated"]
+                .iter()
+                .any(|namespace| udmf.namespace.eq_ignore_ascii_case(namespace)),
+        };
+        if !uses_doom_thing_types {
+            return vec![];
+        }
+
+        map.things
+            .iter()
+            .enumerate()
+            .filter_map(|(thing, thing_info)| {
+                let sprite = doom_thing_sprite(thing_info.thing_type)?;
+                self.sprite(sprite).is_none().then_some(MissingSprite {
+                    thing,
+                    thing_type: thing_info.thing_type,
+                    sprite,
+                })
+            })
+            .collect()
+    }
+}
+
+// Returns each (frame, rotation, mirrored) that a sprite lump name stands for
+fn parse_sprite_lump_name(name: &str) -> Option<Vec<(char, u8, bool)>> {
+    let parse_view = |view: &[u8]| {
+        let frame = view[0] as char;
+        let rotation = (view[1] as char).to_digit(10)?;
+        // Frames go from A up to ], since the characters after Z were used for extra frames
+        (('A'..=']').contains(&frame) && rotation <= 8).then_some((frame, rotation as u8))
+    };
+
+    let bytes = name.as_bytes();
+    match bytes.len() {
+        6 => {
+            let (frame, rotation) = parse_view(&bytes[4..6])?;
+            Some(vec![(frame, rotation, false)])
+        }
+        8 => {
+            let (frame, rotation) = parse_view(&bytes[4..6])?;
+            let (mirrored_frame, mirrored_rotation) = parse_view(&bytes[6..8])?;
+            Some(vec![
+                (frame, rotation, false),
+                (mirrored_frame, mirrored_rotation, true),
+            ])
+        }
+        _ => None,
+    }
+}
+
+impl Wad {
+    pub fn sprites(&self) -> SpriteSet {
+        SpriteSet::new(self.namespace_lumps("S").iter().map(|lump| lump.name()))
+    }
+
+    pub fn sprite_sheet(&self, sprite: &Sprite) -> Result<Picture, WadError> {
+        let lumps = self.namespace_lumps("S");
+        sprite_sheet(sprite, |name| {
+            let lump = lumps
+                .iter()
+                .rev()
+                .find(|lump| lump.name().eq_ignore_ascii_case(name));
+            lump.map(|lump| lump.bytes()).transpose()
+        })
+    }
+}
+
+impl WadStack {
+    pub fn sprites(&self) -> SpriteSet {
+        SpriteSet::new(self.namespace_lumps("S").iter().map(|lump| lump.name()))
+    }
+
+    pub fn sprite_sheet(&self, sprite: &Sprite) -> Result<Picture, WadError> {
+        let lumps = self.namespace_lumps("S");
+        sprite_sheet(sprite, |name| {
+            let lump = lumps
+                .iter()
+                .find(|lump| lump.name().eq_ignore_ascii_case(name));
+            lump.map(|lump| lump.bytes()).transpose()
+        })
+    }
+}
+
+// Lays out every frame of a sprite in a grid: one row per frame, with the all-angles view (if any)
+// in the first column and rotations 1 to 8 in the columns after it
+fn sprite_sheet(
+    sprite: &Sprite,
+    find_lump: impl Fn(&str) -> Result<Option<Vec<u8>>, WadError>,
+) -> Result<Picture, WadError> {
+    const PADDING: i32 = 4;
+
+    let mut pictures: BTreeMap<&str, Picture> = BTreeMap::new();
+    for rotation in sprite.frames.iter().flat_map(|frame| &frame.rotations) {
+        if !pictures.contains_key(rotation.lump.as_str()) {
+            let bytes = find_lump(&rotation.lump)?.ok_or(WadError::MissingLump {
+                lump: rotation.lump.clone(),
+            })?;
+            pictures.insert(&rotation.lump, Picture::decode(&rotation.lump, &bytes)?);
+        }
+    }
+
+    // Where a view is drawn relative to its origin; mirroring flips the offset too, as it does in
+    // the game
+    let view_left = |picture: &Picture, mirrored: bool| {
+        if mirrored {
+            picture.left_offset as i32 - picture.width as i32
+        } else {
+            -(picture.left_offset as i32)
+        }
+    };
+    let pictures = &pictures;
+    let views: Vec<(usize, &SpriteRotation, &Picture)> = sprite
+        .frames
+        .iter()
+        .enumerate()
+        .flat_map(|(row, frame)| {
+            frame
+                .rotations
+                .iter()
+                .map(move |rotation| (row, rotation, &pictures[rotation.lump.as_str()]))
+        })
+        .collect();
+
+    // Every view gets a cell big enough to fit any of them, with their origins lined up
+    let (mut min_x, mut max_x, mut min_y, mut max_y) = (0, 0, 0, 0);
+    for &(_, rotation, picture) in &views {
+        let x = view_left(picture, rotation.mirrored);
+        let y = -(picture.top_offset as i32);
+        min_x = min_x.min(x);
+        max_x = max_x.max(x + picture.width as i32);
+        min_y = min_y.min(y);
+        max_y = max_y.max(y + picture.height as i32);
+    }
+    let cell_width = max_x - min_x + PADDING;
+    let cell_height = max_y - min_y + PADDING;
+
+    let columns = if views.iter().any(|(_, rotation, _)| rotation.rotation > 0) {
+        9
+    } else {
+        1
+    };
+    let width = (columns * cell_width + PADDING) as usize;
+    let height = (sprite.frames.len() as i32 * cell_height + PADDING) as usize;
+    let mut pixels = vec![None; width * height];
+
+    for &(row, rotation, picture) in &views {
+        let left = PADDING + rotation.rotation as i32 * cell_width - min_x
+            + view_left(picture, rotation.mirrored);
+        let top = PADDING + row as i32 * cell_height - min_y - picture.top_offset as i32;
+        for y in 0..picture.height as i32 {
+            for x in 0..picture.width as i32 {
+                let source_x = if rotation.mirrored {
+                    picture.width as i32 - 1 - x
+                } else {
+                    x
+                };
+                if let Some(index) = picture.pixels[(y * picture.width as i32 + source_x) as usize]
+                {
+                    pixels[(top + y) as usize * width + (left + x) as usize] = Some(index);
+                }
+            }
+        }
+    }
+
+    Ok(Picture {
+        width: width as u16,
+        height: height as u16,
+        left_offset: 0,
+        top_offset: 0,
+        pixels,
+    })
+}
+
+// The sprite that a Doom or Doom II thing type is first drawn with, from the game's mobjinfo table.
+// Things that are never visible, such as teleport destinations, have no sprite.
+fn doom_thing_sprite(thing_type: i16) -> Option<&'static str> {
+    Some(match thing_type {
+        // Player starts, and the corpses that use the player sprite
+        1..=4 | 10 | 12 | 15 => "PLAY",
+        // Monsters
+        3004 | 18 => "POSS",
+        9 | 19 => "SPOS",
+        65 => "CPOS",
+        3001 | 20 => "TROO",
+        3002 | 58 | 21 => "SARG",
+        3006 | 23 => "SKUL",
+        3005 | 22 => "HEAD",
+        69 => "BOS2",
+        3003 => "BOSS",
+        68 => "BSPI",
+        71 => "PAIN",
+        66 => "SKEL",
+        67 => "FATT",
+        64 => "VILE",
+        7 => "SPID",
+        16 => "CYBR",
+        84 => "SSWV",
+        72 => "KEEN",
+        88 => "BBRN",
+        // Weapons
+        2001 => "SHOT",
+        82 => "SGN2",
+        2002 => "MGUN",
+        2003 => "LAUN",
+        2004 => "PLAS",
+        2005 => "CSAW",
+        2006 => "BFUG",
+        // Ammo
+        2007 => "CLIP",
+        2048 => "AMMO",
+        2008 => "SHEL",
+        2049 => "SBOX",
+        2010 => "ROCK",
+        2046 => "BROK",
+        2047 => "CELL",
+        17 => "CELP",
+        8 => "BPAK",
+        // Health, armor and powerups
+        2011 => "STIM",
+        2012 => "MEDI",
+        2014 => "BON1",
+        2015 => "BON2",
+        2018 => "ARM1",
+        2019 => "ARM2",
+        83 => "MEGA",
+        2013 => "SOUL",
+        2022 => "PINV",
+        2023 => "PSTR",
+        2024 => "PINS",
+        2025 => "SUIT",
+        2026 => "PMAP",
+        2045 => "PVIS",
+        // Keys
+        5 => "BKEY",
+        40 => "BSKU",
+        13 => "RKEY",
+        38 => "RSKU",
+        6 => "YKEY",
+        39 => "YSKU",
+        // Obstacles and decorations
+        2035 => "BAR1",
+        48 => "ELEC",
+        30 => "COL1",
+        31 => "COL2",
+        32 => "COL3",
+        33 => "COL4",
+        36 => "COL5",
+        37 => "COL6",
+        41 => "CEYE",
+        42 => "FSKU",
+        43 => "TRE1",
+        44 => "TBLU",
+        45 => "TGRN",
+        46 => "TRED",
+        47 => "SMIT",
+        54 => "TRE2",
+        55 => "SMBT",
+        56 => "SMGT",
+        57 => "SMRT",
+        70 => "FCAN",
+        85 => "TLMP",
+        86 => "TLP2",
+        2028 => "COLU",
+        34 => "CAND",
+        35 => "CBRA",
+        49 | 63 => "GOR1",
+        50 | 59 => "GOR2",
+        51 | 61 => "GOR3",
+        52 | 60 => "GOR4",
+        53 | 62 => "GOR5",
+        73 => "HDB1",
+        74 => "HDB2",
+        75 => "HDB3",
+        76 => "HDB4",
+        77 => "HDB5",
+        78 => "HDB6",
+        24 => "POL5",
+        25 => "POL1",
+        26 => "POL6",
+        27 => "POL4",
+        28 => "POL2",
+        29 => "POL3",
+        79 => "POB1",
+        80 => "POB2",
+        81 => "BRS1",
+        _ => return None,
+    })
+}
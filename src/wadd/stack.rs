@@ -0,0 +1,100 @@
+// Doom is normally run with an IWAD plus any number of PWADs loaded on top of it. A WadStack
+// resolves lookups across all of them the same way the game does: later WADs take priority.
+
+use std::collections::HashMap;
+
+use super::{decode_colormap, decode_playpal, Colormap, Lump, MapData, Palette, Wad, WadError};
+
+pub struct WadStack {
+    // In load order, starting with the IWAD
+    pub wads: Vec<Wad>,
+}
+
+impl WadStack {
+    pub fn new(wads: Vec<Wad>) -> WadStack {
+        WadStack { wads }
+    }
+
+    pub fn open(filenames: &[&str]) -> Result<WadStack, WadError> {
+        let wads = filenames
+            .iter()
+            .map(|filename| Wad::open(filename))
+            .collect::<Result<Vec<Wad>, WadError>>()?;
+
+        Ok(WadStack::new(wads))
+    }
+
+    pub fn find_lump(&self, name: &str) -> Option<Lump<'_>> {
+        self.wads.iter().rev().find_map(|wad| {
+            let index = wad.find_lump_index(name)?;
+            Some(Lump {
+                wad,
+                index,
+                entry: &wad.directory[index],
+            })
+        })
+    }
+
+    pub fn lump_bytes(&self, name: &str) -> Option<Result<Vec<u8>, WadError>> {
+        self.find_lump(name).map(|lump| lump.bytes())
+    }
+
+    pub fn palettes(&self) -> Result<Vec<Palette>, WadError> {
+        let lump = self.find_lump("PLAYPAL").ok_or(WadError::MissingLump {
+            lump: "PLAYPAL".to_string(),
+        })?;
+        decode_playpal(lump.entry, &lump.bytes()?)
+    }
+
+    pub fn colormap(&self) -> Result<Colormap, WadError> {
+        let lump = self.find_lump("COLORMAP").ok_or(WadError::MissingLump {
+            lump: "COLORMAP".to_string(),
+        })?;
+        decode_colormap(lump.entry, &lump.bytes()?)
+    }
+
+    // A PWAD's map replaces any map of the same name that was loaded before it; like `map`, names
+    // are compared case-insensitively
+    pub fn maps(&self) -> Vec<&MapData> {
+        let mut maps: HashMap<String, &MapData> = HashMap::new();
+        for wad in &self.wads {
+            for map in wad.maps() {
+                maps.insert(map.name.to_ascii_uppercase(), map);
+            }
+        }
+
+        let mut maps: Vec<&MapData> = maps.into_values().collect();
+        maps.sort_by_key(|map| map.name.clone());
+        maps
+    }
+
+    pub fn map(&self, name: &str) -> Option<&MapData> {
+        self.wads.iter().rev().find_map(|wad| {
+            wad.maps()
+                .iter()
+                .find(|map| map.name.eq_ignore_ascii_case(name))
+        })
+    }
+
+    // Merges a namespace (e.g. "F" for flats, "S" for sprites) across every WAD the way Boom and
+    // its descendants do: a lump that has the same name as an earlier one replaces it in place,
+    // keeping its position (which matters for animated flat ranges), and new lumps are added to
+    // the end
+    pub fn namespace_lumps(&self, namespace: &str) -> Vec<Lump<'_>> {
+        let mut lumps: Vec<Lump> = vec![];
+        let mut positions: HashMap<String, usize> = HashMap::new();
+        for wad in &self.wads {
+            for lump in wad.namespace_lumps(namespace) {
+                let name = lump.name().to_ascii_uppercase();
+                match positions.get(&name) {
+                    Some(&position) => lumps[position] = lump,
+                    None => {
+                        positions.insert(name, lumps.len());
+                        lumps.push(lump);
+                    }
+                }
+            }
+        }
+        lumps
+    }
+}
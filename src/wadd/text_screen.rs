@@ -0,0 +1,191 @@
+// ENDOOM is the text screen shown when the game exits: a copy of the PC's 80x25 text mode screen,
+// with a character and a color attribute for each cell. See https://doomwiki.org/wiki/ENDOOM
+
+use std::fmt::Write;
+
+use super::{Wad, WadError, WadStack};
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct TextCell {
+    // A code page 437 character
+    pub character: u8,
+    // 0 to 15, in the PC's color order (blue is 1, red is 4)
+    pub foreground: u8,
+    // 0 to 7
+    pub background: u8,
+    pub blink: bool,
+}
+
+#[derive(Clone, Debug)]
+pub struct TextScreen {
+    // Row by row
+    pub cells: Vec<TextCell>,
+}
+
+// Code page 437's pictures for the control characters, followed by its characters from 0x80 up
+const CP437_CONTROL: &str = " ☺☻♥♦♣♠•◘○◙♂♀♪♫☼►◄↕‼¶§▬↨↑↓→←∟↔▲▼";
+const CP437_HIGH: &str = "ÇüéâäàåçêëèïîìÄÅÉæÆôöòûùÿÖÜ¢£¥₧ƒáíóúñÑªº¿⌐¬½¼¡«»░▒▓│┤╡╢╖╕╣║╗╝╜╛┐└┴┬├─┼╞╟╚╔╩╦╠═╬╧╨╤╥╙╘╒╓╫╪┘┌█▄▌▐▀αßΓπΣσµτΦΘΩδ∞φε∩≡±≥≤⌠⌡÷≈°∙·√ⁿ²■\u{a0}";
+
+// The 16 text mode colors, in the PC's order
+const VGA_COLORS: [&str; 16] = [
+    "#000000", "#0000aa", "#00aa00", "#00aaaa", "#aa0000", "#aa00aa", "#aa5500", "#aaaaaa",
+    "#555555", "#5555ff", "#55ff55", "#55ffff", "#ff5555", "#ff55ff", "#ffff55", "#ffffff",
+];
+
+// ANSI numbers its colors with red and blue the other way round from the PC
+const VGA_TO_ANSI: [u8; 8] = [0, 4, 2, 6, 1, 5, 3, 7];
+
+impl TextScreen {
+    pub const WIDTH: usize = 80;
+    pub const HEIGHT: usize = 25;
+
+    pub fn decode(lump: &str, bytes: &[u8]) -> Result<TextScreen, WadError> {
+        // A character byte and an attribute byte for each cell: the foreground color in the low 4
+        // bits, the background color in the next 3, and the blink flag in the top one
+        if bytes.len() < TextScreen::WIDTH * TextScreen::HEIGHT * 2 {
+            return Err(WadError::InvalidLump {
+                lump: lump.to_string(),
+                message: "too short to be an 80x25 text screen".to_string(),
+            });
+        }
+
+        let cells = bytes
+            .chunks_exact(2)
+            .take(TextScreen::WIDTH * TextScreen::HEIGHT)
+            .map(|cell| TextCell {
+                character: cell[0],
+                foreground: cell[1] & 0x0F,
+                background: (cell[1] >> 4) & 0x07,
+                blink: cell[1] & 0x80 != 0,
+            })
+            .collect();
+        Ok(TextScreen { cells })
+    }
+
+    pub fn rows(&self) -> impl Iterator<Item = &[TextCell]> {
+        self.cells.chunks(TextScreen::WIDTH)
+    }
+
+    // Plain text, with the characters mapped to their Unicode equivalents
+    pub fn to_text(&self) -> String {
+        let mut text = String::new();
+        for row in self.rows() {
+            let line: String = row
+                .iter()
+                .map(|cell| cp437_to_char(cell.character))
+                .collect();
+            text.push_str(line.trim_end());
+            text.push('\n');
+        }
+        text
+    }
+
+    // Text with ANSI escape codes for the colors, for showing in a terminal
+    pub fn to_ansi(&self) -> String {
+        let mut ansi = String::new();
+        for row in self.rows() {
+            let mut previous = None;
+            for cell in row {
+                let attributes = (cell.foreground, cell.background, cell.blink);
+                if previous != Some(attributes) {
+                    let foreground = VGA_TO_ANSI[cell.foreground as usize & 7];
+                    let foreground = if cell.foreground >= 8 {
+                        90 + foreground
+                    } else {
+                        30 + foreground
+                    };
+                    let background = 40 + VGA_TO_ANSI[cell.background as usize];
+                    let blink = if cell.blink { ";5" } else { "" };
+                    let _ = write!(ansi, "\x1B[0;{};{}{}m", foreground, background, blink);
+                    previous = Some(attributes);
+                }
+                ansi.push(cp437_to_char(cell.character));
+            }
+            ansi.push_str("\x1B[0m\n");
+        }
+        ansi
+    }
+
+    // A standalone HTML page that shows the screen the way it looks in DOS
+    pub fn to_html(&self, title: &str) -> String {
+        let mut screen = String::new();
+        for row in self.rows() {
+            for cell in row {
+                let class = if cell.blink { " class=\"blink\"" } else { "" };
+                let character = match cp437_to_char(cell.character) {
+                    '<' => "&lt;".to_string(),
+                    '>' => "&gt;".to_string(),
+                    '&' => "&amp;".to_string(),
+                    c => c.to_string(),
+                };
+                let _ = write!(
+                    screen,
+                    r#"<span style="color: {}; background: {}"{}>{}</span>"#,
+                    VGA_COLORS[cell.foreground as usize],
+                    VGA_COLORS[cell.background as usize],
+                    class,
+                    character
+                );
+            }
+            screen.push('\n');
+        }
+
+        format!(
+            r#"<!DOCTYPE html>
+<html lang="en-US">
+<head>
+    <meta charset="utf-8">
+    <title>{}</title>
+    <style>
+        html {{
+            background: black;
+            color: white;
+        }}
+        pre {{
+            font-family: "Perfect DOS VGA 437", "Consolas", monospace;
+            line-height: 1;
+        }}
+        .blink {{
+            animation: blink 1s step-end infinite;
+        }}
+        @keyframes blink {{
+            50% {{ color: transparent; }}
+        }}
+    </style>
+    <meta name="viewport" content="width=device-width, initial-scale=1.0, viewport-fit=cover">
+</head>
+<body>
+    <pre>{}</pre>
+</body>
+"#,
+            title, screen
+        )
+    }
+}
+
+pub fn cp437_to_char(character: u8) -> char {
+    match character {
+        0x00..=0x1F => CP437_CONTROL.chars().nth(character as usize).unwrap(),
+        0x7F => '⌂',
+        0x80..=0xFF => CP437_HIGH.chars().nth(character as usize - 0x80).unwrap(),
+        _ => character as char,
+    }
+}
+
+impl Wad {
+    pub fn endoom(&self) -> Result<TextScreen, WadError> {
+        let entry = self.find_lump("ENDOOM").ok_or(WadError::MissingLump {
+            lump: "ENDOOM".to_string(),
+        })?;
+        TextScreen::decode(&entry.name, &self.lump_bytes(entry)?)
+    }
+}
+
+impl WadStack {
+    pub fn endoom(&self) -> Result<TextScreen, WadError> {
+        let lump = self.find_lump("ENDOOM").ok_or(WadError::MissingLump {
+            lump: "ENDOOM".to_string(),
+        })?;
+        TextScreen::decode(lump.name(), &lump.bytes()?)
+    }
+}
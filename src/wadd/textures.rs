@@ -0,0 +1,240 @@
+// Wall textures are built out of patches (pictures) at given offsets. PNAMES lists the patches by
+// name, and TEXTURE1 (plus TEXTURE2 in registered Doom) define the textures in terms of those.
+// See https://doomwiki.org/wiki/TEXTURE1_and_TEXTURE2 and https://doomwiki.org/wiki/PNAMES
+
+use super::{buf_to_string, Picture, Wad, WadError, WadStack};
+
+#[derive(Clone, Debug)]
+pub struct TextureDef {
+    pub name: String,
+    pub masked: bool,
+    pub width: i16,
+    pub height: i16,
+    pub patches: Vec<TexturePatch>,
+}
+
+#[derive(Clone, Debug)]
+pub struct TexturePatch {
+    pub origin_x: i16,
+    pub origin_y: i16,
+    // Index into PNAMES
+    pub patch: u16,
+}
+
+#[derive(Clone, Debug)]
+pub struct TextureSet {
+    pub patch_names: Vec<String>,
+    // TEXTURE1 followed by TEXTURE2
+    pub textures: Vec<TextureDef>,
+}
+
+impl TextureSet {
+    // If more than one texture has the same name, the game uses the first one
+    pub fn texture(&self, name: &str) -> Option<&TextureDef> {
+        self.textures
+            .iter()
+            .find(|texture| texture.name.eq_ignore_ascii_case(name))
+    }
+
+    // The name of the patch a texture patch refers to, if it's in range
+    pub fn patch_name(&self, patch: &TexturePatch) -> Option<&str> {
+        self.patch_names
+            .get(patch.patch as usize)
+            .map(String::as_str)
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct ComposedTexture {
+    pub picture: Picture,
+    // Patches that couldn't be found, either because there's no lump with that name or because the
+    // texture refers to a PNAMES entry that doesn't exist (shown as its index). The game refuses to
+    // start in either case.
+    pub missing_patches: Vec<String>,
+}
+
+impl Wad {
+    pub fn textures(&self) -> Result<TextureSet, WadError> {
+        decode_texture_set(|name| {
+            self.find_lump(name)
+                .map(|entry| self.lump_bytes(entry))
+                .transpose()
+        })
+    }
+
+    pub fn compose_texture(
+        &self,
+        textures: &TextureSet,
+        texture: &TextureDef,
+    ) -> Result<ComposedTexture, WadError> {
+        compose_texture(textures, texture, |name| {
+            self.find_lump(name)
+                .map(|entry| self.lump_bytes(entry))
+                .transpose()
+        })
+    }
+}
+
+impl WadStack {
+    pub fn textures(&self) -> Result<TextureSet, WadError> {
+        decode_texture_set(|name| self.lump_bytes(name).transpose())
+    }
+
+    pub fn compose_texture(
+        &self,
+        textures: &TextureSet,
+        texture: &TextureDef,
+    ) -> Result<ComposedTexture, WadError> {
+        compose_texture(textures, texture, |name| self.lump_bytes(name).transpose())
+    }
+}
+
+fn decode_texture_set(
+    find_lump: impl Fn(&str) -> Result<Option<Vec<u8>>, WadError>,
+) -> Result<TextureSet, WadError> {
+    let missing = |lump: &str| WadError::MissingLump {
+        lump: lump.to_string(),
+    };
+
+    let patch_names = decode_pnames(&find_lump("PNAMES")?.ok_or_else(|| missing("PNAMES"))?)?;
+    let mut textures = decode_textures(
+        "TEXTURE1",
+        &find_lump("TEXTURE1")?.ok_or_else(|| missing("TEXTURE1"))?,
+    )?;
+    if let Some(bytes) = find_lump("TEXTURE2")? {
+        textures.extend(decode_textures("TEXTURE2", &bytes)?);
+    }
+
+    Ok(TextureSet {
+        patch_names,
+        textures,
+    })
+}
+
+fn compose_texture(
+    textures: &TextureSet,
+    texture: &TextureDef,
+    find_lump: impl Fn(&str) -> Result<Option<Vec<u8>>, WadError>,
+) -> Result<ComposedTexture, WadError> {
+    let width = texture.width.max(0) as u16;
+    let height = texture.height.max(0) as u16;
+    let mut pixels = vec![None; width as usize * height as usize];
+    let mut missing_patches = vec![];
+
+    for texture_patch in &texture.patches {
+        let Some(patch_name) = textures.patch_name(texture_patch) else {
+            missing_patches.push(format!("#{}", texture_patch.patch));
+            continue;
+        };
+        let Some(bytes) = find_lump(patch_name)? else {
+            missing_patches.push(patch_name.to_string());
+            continue;
+        };
+        let patch = Picture::decode(patch_name, &bytes)?;
+
+        // Patches are drawn in order, each on top of the ones before it, clipped to the texture
+        for y in 0..patch.height as i32 {
+            let texture_y = texture_patch.origin_y as i32 + y;
+            if texture_y < 0 || texture_y >= height as i32 {
+                continue;
+            }
+            for x in 0..patch.width as i32 {
+                let texture_x = texture_patch.origin_x as i32 + x;
+                if texture_x < 0 || texture_x >= width as i32 {
+                    continue;
+                }
+                if let Some(index) = patch.pixels[(y * patch.width as i32 + x) as usize] {
+                    pixels[(texture_y * width as i32 + texture_x) as usize] = Some(index);
+                }
+            }
+        }
+    }
+
+    Ok(ComposedTexture {
+        picture: Picture {
+            width,
+            height,
+            left_offset: 0,
+            top_offset: 0,
+            pixels,
+        },
+        missing_patches,
+    })
+}
+
+fn decode_pnames(bytes: &[u8]) -> Result<Vec<String>, WadError> {
+    // A count, followed by that many 8 byte names
+    let invalid = |message: &str| WadError::InvalidLump {
+        lump: "PNAMES".to_string(),
+        message: message.to_string(),
+    };
+
+    let count = read_i32(bytes, 0).ok_or_else(|| invalid("missing the number of patches"))?;
+    let names_buf = bytes
+        .get(4..4 + count.max(0) as usize * 8)
+        .ok_or_else(|| invalid("has fewer names than it claims"))?;
+
+    names_buf
+        .chunks_exact(8)
+        .enumerate()
+        .map(|(index, name_buf)| {
+            buf_to_string(name_buf).map_err(|_| WadError::InvalidName {
+                lump: "PNAMES".to_string(),
+                index: Some(index),
+            })
+        })
+        .collect()
+}
+
+fn decode_textures(lump: &str, bytes: &[u8]) -> Result<Vec<TextureDef>, WadError> {
+    // A count, followed by that many offsets to the texture definitions. Each definition is a 22
+    // byte header followed by a 10 byte record for each patch.
+    let invalid = |message: String| WadError::InvalidLump {
+        lump: lump.to_string(),
+        message,
+    };
+
+    let count =
+        read_i32(bytes, 0).ok_or_else(|| invalid("missing the number of textures".to_string()))?;
+    let mut textures = vec![];
+    for index in 0..count.max(0) as usize {
+        let offset = read_i32(bytes, 4 + index * 4)
+            .ok_or_else(|| invalid("has fewer textures than it claims".to_string()))?
+            as u32 as usize;
+        let header = bytes
+            .get(offset..offset + 22)
+            .ok_or_else(|| invalid(format!("texture {} is past the end of the lump", index)))?;
+
+        let name = buf_to_string(&header[0..8]).map_err(|_| WadError::InvalidName {
+            lump: lump.to_string(),
+            index: Some(index),
+        })?;
+        let patch_count = i16::from_le_bytes([header[20], header[21]]).max(0) as usize;
+        let patches_buf = bytes
+            .get(offset + 22..offset + 22 + patch_count * 10)
+            .ok_or_else(|| invalid(format!("{}'s patches run past the end of the lump", name)))?;
+
+        textures.push(TextureDef {
+            masked: read_i32(header, 8) != Some(0),
+            width: i16::from_le_bytes([header[12], header[13]]),
+            height: i16::from_le_bytes([header[14], header[15]]),
+            // The 4 bytes after the size are an obsolete column directory pointer
+            patches: patches_buf
+                .chunks_exact(10)
+                .map(|patch_buf| TexturePatch {
+                    origin_x: i16::from_le_bytes([patch_buf[0], patch_buf[1]]),
+                    origin_y: i16::from_le_bytes([patch_buf[2], patch_buf[3]]),
+                    patch: u16::from_le_bytes([patch_buf[4], patch_buf[5]]),
+                    // The remaining "step dir" and "colormap" fields are unused
+                })
+                .collect(),
+            name,
+        });
+    }
+    Ok(textures)
+}
+
+fn read_i32(bytes: &[u8], offset: usize) -> Option<i32> {
+    let buf = bytes.get(offset..offset + 4)?;
+    Some(i32::from_le_bytes(buf.try_into().unwrap()))
+}
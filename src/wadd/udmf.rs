@@ -0,0 +1,667 @@
+// UDMF ("Universal Doom Map Format") maps store everything in a single TEXTMAP lump, as text.
+// This is based on the spec at https://github.com/ZDoom/gzdoom/blob/master/specs/udmf.txt
+
+use std::collections::HashMap;
+use std::iter::Peekable;
+use std::str::CharIndices;
+
+use fixedstr::fstr;
+
+use super::{LineDef, Sector, SideDef, Thing, Vertex, WadError};
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum Value {
+    Int(i64),
+    Float(f64),
+    Bool(bool),
+    Str(String),
+    // Any other bare word; the spec only defines `true` and `false`, but ports are free to add more
+    Keyword(String),
+}
+
+impl Value {
+    pub fn as_f64(&self) -> Option<f64> {
+        match self {
+            Value::Int(int) => Some(*int as f64),
+            Value::Float(float) => Some(*float),
+            _ => None,
+        }
+    }
+
+    pub fn as_i64(&self) -> Option<i64> {
+        match self {
+            Value::Int(int) => Some(*int),
+            Value::Float(float) => Some(*float as i64),
+            _ => None,
+        }
+    }
+
+    pub fn as_bool(&self) -> Option<bool> {
+        match self {
+            Value::Bool(bool) => Some(*bool),
+            _ => None,
+        }
+    }
+
+    pub fn as_str(&self) -> Option<&str> {
+        match self {
+            Value::Str(str) => Some(str),
+            _ => None,
+        }
+    }
+}
+
+// Keys are always stored in lowercase, as UDMF identifiers are case insensitive
+pub type Properties = HashMap<String, Value>;
+
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct UdmfMap {
+    pub namespace: String,
+    pub vertexes: Vec<UdmfVertex>,
+    pub linedefs: Vec<UdmfLineDef>,
+    pub sidedefs: Vec<UdmfSideDef>,
+    pub sectors: Vec<UdmfSector>,
+    pub things: Vec<UdmfThing>,
+    // Global assignments other than `namespace`
+    pub properties: Properties,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct UdmfVertex {
+    pub x: f64,
+    pub y: f64,
+    pub properties: Properties,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct UdmfLineDef {
+    pub id: i32,
+    pub vertex_begin: i32,
+    pub vertex_end: i32,
+    // Only the flags that exist in the Doom format are kept here, using the same bits; any others
+    // are left in `properties`
+    pub flags: i16,
+    pub special: i32,
+    pub args: [i32; 5],
+    pub sidedef_right: i32,
+    pub sidedef_left: i32,
+    pub properties: Properties,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct UdmfSideDef {
+    pub x: i32,
+    pub y: i32,
+    pub upper_texture: String,
+    pub lower_texture: String,
+    pub middle_texture: String,
+    pub sector: i32,
+    pub properties: Properties,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct UdmfSector {
+    pub floor_height: i32,
+    pub ceiling_height: i32,
+    pub floor_texture: String,
+    pub ceiling_texture: String,
+    pub light_level: i32,
+    pub special: i32,
+    pub id: i32,
+    pub properties: Properties,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct UdmfThing {
+    pub id: i32,
+    pub x: f64,
+    pub y: f64,
+    pub height: f64,
+    pub angle: i32,
+    pub thing_type: i32,
+    // As with linedefs, only the flags that have a Doom-format equivalent are kept here
+    pub flags: i16,
+    pub special: i32,
+    pub args: [i32; 5],
+    pub properties: Properties,
+}
+
+const NO_TEXTURE_PLACEHOLDER: &str = "-";
+
+// Doom-format linedef flag bits and their UDMF equivalents
+const LINEDEF_FLAGS: [(&str, i16); 9] = [
+    ("blocking", 0x0001),
+    ("blockmonsters", 0x0002),
+    ("twosided", 0x0004),
+    ("dontpegtop", 0x0008),
+    ("dontpegbottom", 0x0010),
+    ("secret", 0x0020),
+    ("blocksound", 0x0040),
+    ("dontdraw", 0x0080),
+    ("mapped", 0x0100),
+];
+
+const THING_FLAG_EASY: i16 = 0x0001;
+const THING_FLAG_MEDIUM: i16 = 0x0002;
+const THING_FLAG_HARD: i16 = 0x0004;
+const THING_FLAG_AMBUSH: i16 = 0x0008;
+const THING_FLAG_MULTIPLAYER_ONLY: i16 = 0x0010;
+
+impl UdmfMap {
+    pub fn parse(text: &str) -> Result<UdmfMap, WadError> {
+        let mut tokenizer = Tokenizer::new(text);
+        let mut map = UdmfMap::default();
+
+        while let Some((line, token)) = tokenizer.next_token()? {
+            let key = match token {
+                Token::Identifier(key) => key.to_ascii_lowercase(),
+                _ => return Err(syntax_error(line, "expected an identifier")),
+            };
+
+            match tokenizer.next_token()? {
+                Some((_, Token::Symbol('='))) => {
+                    let value = parse_value(&mut tokenizer, line)?;
+                    if key == "namespace" {
+                        map.namespace = value.as_str().unwrap_or_default().to_string();
+                    } else {
+                        map.properties.insert(key, value);
+                    }
+                }
+                Some((_, Token::Symbol('{'))) => {
+                    let properties = parse_block(&mut tokenizer, line)?;
+                    match key.as_str() {
+                        "vertex" => map.vertexes.push(decode_vertex(properties, line)?),
+                        "linedef" => map.linedefs.push(decode_linedef(properties, line)?),
+                        "sidedef" => map.sidedefs.push(decode_sidedef(properties, line)?),
+                        "sector" => map.sectors.push(decode_sector(properties)),
+                        "thing" => map.things.push(decode_thing(properties, line)?),
+                        _ => (), // blocks we don't know about are skipped, per the spec
+                    }
+                }
+                _ => return Err(syntax_error(line, "expected '=' or '{'")),
+            }
+        }
+
+        Ok(map)
+    }
+
+    // The following produce the map's data in the form used by Doom-format maps, so that they can
+    // populate `MapData`; fractional coordinates are rounded, and long texture names truncated
+
+    pub fn to_vertexes(&self) -> Vec<Vertex> {
+        self.vertexes
+            .iter()
+            .map(|vertex| Vertex {
+                x: vertex.x.round() as i16,
+                y: vertex.y.round() as i16,
+            })
+            .collect()
+    }
+
+    pub fn to_linedefs(&self) -> Vec<LineDef> {
+        self.linedefs
+            .iter()
+            .map(|linedef| LineDef {
+                vertex_begin: linedef.vertex_begin as i16,
+                vertex_end: linedef.vertex_end as i16,
+                flags: linedef.flags,
+                line_type: linedef.special as i16,
+                // A linedef's id doubles as the tag of the sectors its special acts upon
+                sector_tag: linedef.id.max(0) as i16,
+                sidedef_right: linedef.sidedef_right as i16,
+                sidedef_left: linedef.sidedef_left as i16,
+            })
+            .collect()
+    }
+
+    pub fn to_sidedefs(&self) -> Vec<SideDef> {
+        let texture =
+            |name: &str| Some(texture_fstr(name)).filter(|str| str != NO_TEXTURE_PLACEHOLDER);
+
+        self.sidedefs
+            .iter()
+            .map(|sidedef| SideDef {
+                x: sidedef.x as i16,
+                y: sidedef.y as i16,
+                upper_texture: texture(&sidedef.upper_texture),
+                lower_texture: texture(&sidedef.lower_texture),
+                middle_texture: texture(&sidedef.middle_texture),
+                sector: sidedef.sector as u16,
+            })
+            .collect()
+    }
+
+    pub fn to_sectors(&self) -> Vec<Sector> {
+        self.sectors
+            .iter()
+            .map(|sector| Sector {
+                floor_height: sector.floor_height as i16,
+                ceiling_height: sector.ceiling_height as i16,
+                floor_texture: texture_fstr(&sector.floor_texture),
+                ceiling_texture: texture_fstr(&sector.ceiling_texture),
+                light_level: sector.light_level as i16,
+                special: sector.special as u16,
+                sector_tag: sector.id.max(0) as u16,
+            })
+            .collect()
+    }
+
+    pub fn to_things(&self) -> Vec<Thing> {
+        self.things
+            .iter()
+            .map(|thing| Thing {
+                x: thing.x.round() as i16,
+                y: thing.y.round() as i16,
+                angle: thing.angle as i16,
+                thing_type: thing.thing_type as i16,
+                spawn_flags: thing.flags,
+            })
+            .collect()
+    }
+}
+
+fn texture_fstr(name: &str) -> fstr<8> {
+    // fstr::from truncates too, but complains on stderr about it
+    let end = (0..=name.len().min(8))
+        .rev()
+        .find(|&i| name.is_char_boundary(i))
+        .unwrap_or(0);
+    fstr::from(&name[..end])
+}
+
+fn syntax_error(line: usize, message: &str) -> WadError {
+    WadError::InvalidTextMap {
+        line,
+        message: message.to_string(),
+    }
+}
+
+fn parse_value(tokenizer: &mut Tokenizer, line: usize) -> Result<Value, WadError> {
+    let value = match tokenizer.next_token()? {
+        Some((_, Token::Value(value))) => value,
+        Some((_, Token::Identifier(keyword))) => match keyword.to_ascii_lowercase().as_str() {
+            "true" => Value::Bool(true),
+            "false" => Value::Bool(false),
+            _ => Value::Keyword(keyword),
+        },
+        _ => return Err(syntax_error(line, "expected a value")),
+    };
+
+    match tokenizer.next_token()? {
+        Some((_, Token::Symbol(';'))) => Ok(value),
+        _ => Err(syntax_error(line, "expected ';'")),
+    }
+}
+
+fn parse_block(tokenizer: &mut Tokenizer, line: usize) -> Result<Properties, WadError> {
+    let mut properties = Properties::new();
+
+    loop {
+        match tokenizer.next_token()? {
+            Some((_, Token::Symbol('}'))) => return Ok(properties),
+            Some((line, Token::Identifier(key))) => {
+                match tokenizer.next_token()? {
+                    Some((_, Token::Symbol('='))) => (),
+                    _ => return Err(syntax_error(line, "expected '='")),
+                }
+                let value = parse_value(tokenizer, line)?;
+                properties.insert(key.to_ascii_lowercase(), value);
+            }
+            Some((line, _)) => return Err(syntax_error(line, "expected an identifier or '}'")),
+            None => return Err(syntax_error(line, "block is never closed")),
+        }
+    }
+}
+
+fn take_f64(properties: &mut Properties, key: &str) -> Option<f64> {
+    take_if(properties, key, Value::as_f64)
+}
+
+fn take_i32(properties: &mut Properties, key: &str) -> Option<i32> {
+    take_if(properties, key, |value| {
+        value.as_i64().map(|int| int as i32)
+    })
+}
+
+fn take_bool(properties: &mut Properties, key: &str) -> bool {
+    take_if(properties, key, Value::as_bool).unwrap_or(false)
+}
+
+fn take_string(properties: &mut Properties, key: &str) -> String {
+    take_if(properties, key, |value| value.as_str().map(str::to_string))
+        .unwrap_or_else(|| NO_TEXTURE_PLACEHOLDER.to_string())
+}
+
+// Removes the given key from `properties` if its value can be converted, and leaves it alone
+// otherwise so that nothing is lost
+fn take_if<T>(
+    properties: &mut Properties,
+    key: &str,
+    convert: impl Fn(&Value) -> Option<T>,
+) -> Option<T> {
+    let converted = convert(properties.get(key)?)?;
+    properties.remove(key);
+    Some(converted)
+}
+
+fn take_args(properties: &mut Properties) -> [i32; 5] {
+    let mut args = [0; 5];
+    for (i, arg) in args.iter_mut().enumerate() {
+        *arg = take_i32(properties, &format!("arg{}", i)).unwrap_or(0);
+    }
+    args
+}
+
+fn required<T>(value: Option<T>, line: usize, key: &str) -> Result<T, WadError> {
+    value.ok_or_else(|| syntax_error(line, &format!("missing required key '{}'", key)))
+}
+
+fn decode_vertex(mut properties: Properties, line: usize) -> Result<UdmfVertex, WadError> {
+    Ok(UdmfVertex {
+        x: required(take_f64(&mut properties, "x"), line, "x")?,
+        y: required(take_f64(&mut properties, "y"), line, "y")?,
+        properties,
+    })
+}
+
+fn decode_linedef(mut properties: Properties, line: usize) -> Result<UdmfLineDef, WadError> {
+    let mut flags = 0;
+    for (key, bit) in LINEDEF_FLAGS {
+        if take_bool(&mut properties, key) {
+            flags |= bit;
+        }
+    }
+
+    Ok(UdmfLineDef {
+        id: take_i32(&mut properties, "id").unwrap_or(-1),
+        vertex_begin: required(take_i32(&mut properties, "v1"), line, "v1")?,
+        vertex_end: required(take_i32(&mut properties, "v2"), line, "v2")?,
+        flags,
+        special: take_i32(&mut properties, "special").unwrap_or(0),
+        args: take_args(&mut properties),
+        sidedef_right: required(take_i32(&mut properties, "sidefront"), line, "sidefront")?,
+        sidedef_left: take_i32(&mut properties, "sideback").unwrap_or(-1),
+        properties,
+    })
+}
+
+fn decode_sidedef(mut properties: Properties, line: usize) -> Result<UdmfSideDef, WadError> {
+    Ok(UdmfSideDef {
+        x: take_i32(&mut properties, "offsetx").unwrap_or(0),
+        y: take_i32(&mut properties, "offsety").unwrap_or(0),
+        upper_texture: take_string(&mut properties, "texturetop"),
+        lower_texture: take_string(&mut properties, "texturebottom"),
+        middle_texture: take_string(&mut properties, "texturemiddle"),
+        sector: required(take_i32(&mut properties, "sector"), line, "sector")?,
+        properties,
+    })
+}
+
+fn decode_sector(mut properties: Properties) -> UdmfSector {
+    UdmfSector {
+        floor_height: take_i32(&mut properties, "heightfloor").unwrap_or(0),
+        ceiling_height: take_i32(&mut properties, "heightceiling").unwrap_or(0),
+        floor_texture: take_string(&mut properties, "texturefloor"),
+        ceiling_texture: take_string(&mut properties, "textureceiling"),
+        light_level: take_i32(&mut properties, "lightlevel").unwrap_or(160),
+        special: take_i32(&mut properties, "special").unwrap_or(0),
+        id: take_i32(&mut properties, "id").unwrap_or(0),
+        properties,
+    }
+}
+
+fn decode_thing(mut properties: Properties, line: usize) -> Result<UdmfThing, WadError> {
+    // UDMF has a flag per skill level, where Doom's format lumps them into three
+    let mut flags = 0;
+    if take_bool(&mut properties, "skill1") | take_bool(&mut properties, "skill2") {
+        flags |= THING_FLAG_EASY;
+    }
+    if take_bool(&mut properties, "skill3") {
+        flags |= THING_FLAG_MEDIUM;
+    }
+    if take_bool(&mut properties, "skill4") | take_bool(&mut properties, "skill5") {
+        flags |= THING_FLAG_HARD;
+    }
+    if take_bool(&mut properties, "ambush") {
+        flags |= THING_FLAG_AMBUSH;
+    }
+    if !take_bool(&mut properties, "single") {
+        flags |= THING_FLAG_MULTIPLAYER_ONLY;
+    }
+
+    Ok(UdmfThing {
+        id: take_i32(&mut properties, "id").unwrap_or(0),
+        x: required(take_f64(&mut properties, "x"), line, "x")?,
+        y: required(take_f64(&mut properties, "y"), line, "y")?,
+        height: take_f64(&mut properties, "height").unwrap_or(0.0),
+        angle: take_i32(&mut properties, "angle").unwrap_or(0),
+        thing_type: required(take_i32(&mut properties, "type"), line, "type")?,
+        flags,
+        special: take_i32(&mut properties, "special").unwrap_or(0),
+        args: take_args(&mut properties),
+        properties,
+    })
+}
+
+//
+
+#[derive(Debug)]
+enum Token {
+    Identifier(String),
+    Value(Value),
+    Symbol(char),
+}
+
+struct Tokenizer<'a> {
+    text: &'a str,
+    chars: Peekable<CharIndices<'a>>,
+    line: usize,
+}
+
+impl<'a> Tokenizer<'a> {
+    fn new(text: &'a str) -> Tokenizer<'a> {
+        Tokenizer {
+            text,
+            chars: text.char_indices().peekable(),
+            line: 1,
+        }
+    }
+
+    // Returns the next token along with the line it's on, or None at the end of the text
+    fn next_token(&mut self) -> Result<Option<(usize, Token)>, WadError> {
+        self.skip_whitespace_and_comments()?;
+
+        let line = self.line;
+        let (start, c) = match self.chars.next() {
+            Some(next) => next,
+            None => return Ok(None),
+        };
+
+        let token = match c {
+            '{' | '}' | '=' | ';' => Token::Symbol(c),
+            '"' => Token::Value(Value::Str(self.quoted_string()?)),
+            _ if c.is_ascii_digit() || c == '-' || c == '+' || c == '.' => {
+                let end = self.take_while(|c| c.is_ascii_alphanumeric() || "+-.".contains(c));
+                Token::Value(parse_number(&self.text[start..end], line)?)
+            }
+            _ if c.is_ascii_alphabetic() || c == '_' => {
+                let end = self.take_while(|c| c.is_ascii_alphanumeric() || c == '_');
+                Token::Identifier(self.text[start..end].to_string())
+            }
+            _ => return Err(syntax_error(line, &format!("unexpected character {:?}", c))),
+        };
+
+        Ok(Some((line, token)))
+    }
+
+    // Consumes characters matching the predicate, returning the offset just past the last one
+    fn take_while(&mut self, predicate: impl Fn(char) -> bool) -> usize {
+        while let Some(&(_, c)) = self.chars.peek() {
+            if !predicate(c) {
+                break;
+            }
+            self.chars.next();
+        }
+        self.chars.peek().map_or(self.text.len(), |&(i, _)| i)
+    }
+
+    fn quoted_string(&mut self) -> Result<String, WadError> {
+        let line = self.line;
+        let mut str = String::new();
+        loop {
+            match self.chars.next() {
+                Some((_, '"')) => return Ok(str),
+                Some((_, '\\')) => match self.chars.next() {
+                    Some((_, c)) => str.push(c),
+                    None => break,
+                },
+                Some((_, c)) => {
+                    if c == '\n' {
+                        self.line += 1;
+                    }
+                    str.push(c)
+                }
+                None => break,
+            }
+        }
+        Err(syntax_error(line, "string is never closed"))
+    }
+
+    fn skip_whitespace_and_comments(&mut self) -> Result<(), WadError> {
+        while let Some(&(i, c)) = self.chars.peek() {
+            if c == '\n' {
+                self.line += 1;
+                self.chars.next();
+            } else if c.is_whitespace() {
+                self.chars.next();
+            } else if self.text[i..].starts_with("//") {
+                self.take_while(|c| c != '\n');
+            } else if self.text[i..].starts_with("/*") {
+                let line = self.line;
+                let end = match self.text[i + 2..].find("*/") {
+                    Some(end) => i + 2 + end + 2,
+                    None => return Err(syntax_error(line, "comment is never closed")),
+                };
+                self.line += self.text[i..end].matches('\n').count();
+                while self.chars.next_if(|&(i, _)| i < end).is_some() {}
+            } else {
+                break;
+            }
+        }
+        Ok(())
+    }
+}
+
+fn parse_number(str: &str, line: usize) -> Result<Value, WadError> {
+    let invalid = || syntax_error(line, &format!("invalid number {:?}", str));
+
+    let (negative, digits) = match str.strip_prefix('-') {
+        Some(digits) => (true, digits),
+        None => (false, str.strip_prefix('+').unwrap_or(str)),
+    };
+
+    let magnitude = if let Some(hex) = digits
+        .strip_prefix("0x")
+        .or_else(|| digits.strip_prefix("0X"))
+    {
+        Value::Int(i64::from_str_radix(hex, 16).map_err(|_| invalid())?)
+    } else if digits.contains(['.', 'e', 'E']) {
+        Value::Float(digits.parse().map_err(|_| invalid())?)
+    } else if digits.len() > 1 && digits.starts_with('0') {
+        Value::Int(i64::from_str_radix(digits, 8).map_err(|_| invalid())?)
+    } else {
+        Value::Int(digits.parse().map_err(|_| invalid())?)
+    };
+
+    Ok(match magnitude {
+        Value::Int(int) if negative => Value::Int(-int),
+        Value::Float(float) if negative => Value::Float(-float),
+        value => value,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn error_line(text: &str) -> usize {
+        match UdmfMap::parse(text) {
+            Err(WadError::InvalidTextMap { line, .. }) => line,
+            result => panic!("expected a syntax error, got {:?}", result),
+        }
+    }
+
+    #[test]
+    fn numbers_in_every_form() {
+        let cases = [
+            ("0x1F", Value::Int(31)),
+            ("-0X1f", Value::Int(-31)),
+            ("017", Value::Int(15)),
+            ("0", Value::Int(0)),
+            ("+3", Value::Int(3)),
+            ("-2", Value::Int(-2)),
+            ("1.5", Value::Float(1.5)),
+            ("-.5", Value::Float(-0.5)),
+            ("1e3", Value::Float(1000.0)),
+            ("+2.5E-1", Value::Float(0.25)),
+        ];
+        for (str, value) in cases {
+            assert_eq!(parse_number(str, 1).unwrap(), value, "{}", str);
+        }
+
+        for str in ["08", "0x", "1.2.3", "12abc"] {
+            assert!(parse_number(str, 1).is_err(), "{}", str);
+        }
+    }
+
+    #[test]
+    fn numbers_are_tokenized_whole() {
+        let map = UdmfMap::parse("a = -0x10; b = 1.5e+2; c = 010;").unwrap();
+        assert_eq!(map.properties["a"], Value::Int(-16));
+        assert_eq!(map.properties["b"], Value::Float(150.0));
+        assert_eq!(map.properties["c"], Value::Int(8));
+    }
+
+    #[test]
+    fn comments_count_towards_line_numbers() {
+        assert_eq!(error_line("/* one\ntwo\nthree */ // four\nfive = ;"), 4);
+        assert_eq!(error_line("// one\n\n/**/ three = ;"), 3);
+        assert_eq!(error_line("one = 1;\n/* never\nclosed"), 2);
+        assert_eq!(error_line("one = \"a\nb\";\nthree = ;"), 3);
+    }
+
+    #[test]
+    fn unknown_blocks_are_skipped() {
+        let map = UdmfMap::parse(
+            "namespace = \"zdoom\";
+            vertex { x = 0; y = 0; }
+            somethingnew { x = 1; label = \"hi\"; }
+            vertex { x = 64.5; y = -8; }",
+        )
+        .unwrap();
+        assert_eq!(map.namespace, "zdoom");
+        assert_eq!(map.vertexes.len(), 2);
+        assert_eq!((map.vertexes[1].x, map.vertexes[1].y), (64.5, -8.0));
+        assert!(map.properties.is_empty());
+    }
+
+    #[test]
+    fn unknown_keys_end_up_in_properties() {
+        let map = UdmfMap::parse(
+            "namespace = \"zdoom\";
+            CustomGlobal = 1;
+            thing { x = 1; y = 2; type = 3001; Health = 2; renderstyle = add; skill3 = true; }",
+        )
+        .unwrap();
+        assert_eq!(map.properties["customglobal"], Value::Int(1));
+
+        let thing = &map.things[0];
+        assert_eq!((thing.x, thing.y, thing.thing_type), (1.0, 2.0, 3001));
+        assert_eq!(thing.properties.len(), 2);
+        assert_eq!(thing.properties["health"], Value::Int(2));
+        assert_eq!(
+            thing.properties["renderstyle"],
+            Value::Keyword("add".to_string())
+        );
+    }
+}
@@ -0,0 +1,655 @@
+// Serializes a Wad back into the on-disk format: a header, followed by the data for each lump in
+// directory order, followed by the directory itself.
+
+use std::collections::HashMap;
+use std::io::Write;
+
+use super::udmf::UdmfMap;
+use super::{
+    decode_hexen_linedefs, decode_hexen_things, decode_linedefs, decode_records, decode_sectors,
+    decode_sidedefs, decode_things, decode_vertexes, lumps_after_map_marker, DirectoryEntry,
+    HexenLineDef, HexenThing, LineDef, MapData, MapFormat, Sector, SideDef, Thing, Vertex, Wad,
+    WadError, WadType,
+};
+
+impl Wad {
+    // Map lumps are re-encoded from `maps` if they've been changed; everything else is written
+    // as-is, from memory if it has been replaced and from the source if not. A WAD laid out the
+    // usual way (lumps in directory order, directory at the end) comes back out byte for byte.
+    pub fn save<W: Write>(&self, mut writer: W) -> Result<(), WadError> {
+        let encoded_map_lumps = self.encode_map_lumps()?;
+
+        let mut lump_sizes = vec![];
+        for (i, entry) in self.directory.iter().enumerate() {
+            lump_sizes.push(match (&entry.data, encoded_map_lumps.get(&i)) {
+                (_, Some(data)) | (Some(data), None) => data.len(),
+                (None, None) => entry.size.max(0) as usize,
+            });
+        }
+
+        const HEADER_SIZE: usize = 12;
+        let directory_offset = HEADER_SIZE + lump_sizes.iter().sum::<usize>();
+
+        let signature = match self.wad_type {
+            WadType::IWAD => b"IWAD",
+            WadType::PWAD => b"PWAD",
+        };
+        writer.write_all(signature)?;
+        writer.write_all(&(self.directory.len() as i32).to_le_bytes())?;
+        writer.write_all(&(directory_offset as i32).to_le_bytes())?;
+
+        for (i, entry) in self.directory.iter().enumerate() {
+            match (&entry.data, encoded_map_lumps.get(&i)) {
+                (_, Some(data)) | (Some(data), None) => writer.write_all(data)?,
+                (None, None) => writer.write_all(&self.lump_bytes(entry)?)?,
+            }
+        }
+
+        let mut offset = HEADER_SIZE;
+        for (entry, size) in self.directory.iter().zip(lump_sizes) {
+            // Tools disagree on what offset to give empty lumps such as map markers; keep whichever
+            // convention the source used
+            let lump_offset = if size == 0 && entry.offset == 0 {
+                0
+            } else {
+                offset
+            };
+            writer.write_all(&(lump_offset as i32).to_le_bytes())?;
+            writer.write_all(&(size as i32).to_le_bytes())?;
+            writer.write_all(&name_to_buf(&entry.name))?;
+            offset += size;
+        }
+
+        writer.flush()?;
+        Ok(())
+    }
+
+    // Encodes the map lumps that have changed in every decoded map, keyed by their index in the
+    // directory
+    pub(super) fn encode_map_lumps(&self) -> Result<HashMap<usize, Vec<u8>>, WadError> {
+        // Maps that were never decoded can't have been changed
+        let Some(decoded) = self.decoded_maps() else {
+            return Ok(HashMap::new());
+        };
+
+        let mut encoded = HashMap::new();
+        for (map, &marker_index) in decoded.maps.iter().zip(&decoded.markers) {
+            let lumps = lumps_after_map_marker(&self.directory, marker_index).unwrap_or_default();
+            for (offset, entry) in lumps.iter().enumerate() {
+                if let Some(data) = encode_map_lump(map, entry, self.lump_bytes(entry)?)? {
+                    encoded.insert(marker_index + 1 + offset, data);
+                }
+            }
+        }
+        Ok(encoded)
+    }
+}
+
+// Returns None for lumps that haven't changed, or that aren't generated from `MapData`, which are
+// copied from the source unchanged
+fn encode_map_lump(
+    map: &MapData,
+    entry: &DirectoryEntry,
+    bytes: Vec<u8>,
+) -> Result<Option<Vec<u8>>, WadError> {
+    Ok(match (&map.format, entry.name.as_str()) {
+        (MapFormat::Doom, "THINGS") => {
+            let original = decode_records(entry, bytes, decode_things)?;
+            encode_if_changed(&map.things, &original, encode_thing)
+        }
+        (MapFormat::Doom, "LINEDEFS") => {
+            let original = decode_records(entry, bytes, decode_linedefs)?;
+            encode_if_changed(&map.linedefs, &original, encode_linedef)
+        }
+        (MapFormat::Hexen { things, .. }, "THINGS") => {
+            let original = decode_records(entry, bytes, decode_hexen_things)?;
+            let things = merge_hexen_things(map, things, &original)?;
+            encode_if_changed(&things, &original, encode_hexen_thing)
+        }
+        (MapFormat::Hexen { linedefs, .. }, "LINEDEFS") => {
+            let original = decode_records(entry, bytes, decode_hexen_linedefs)?;
+            let linedefs = merge_hexen_linedefs(map, linedefs, &original)?;
+            encode_if_changed(&linedefs, &original, encode_hexen_linedef)
+        }
+        (MapFormat::Doom | MapFormat::Hexen { .. }, "SIDEDEFS") => {
+            let original = decode_records(entry, bytes, decode_sidedefs)?;
+            encode_if_changed(&map.sidedefs, &original, encode_sidedef)
+        }
+        (MapFormat::Doom | MapFormat::Hexen { .. }, "VERTEXES") => {
+            let original = decode_records(entry, bytes, decode_vertexes)?;
+            encode_if_changed(&map.vertexes, &original, encode_vertex)
+        }
+        (MapFormat::Doom | MapFormat::Hexen { .. }, "SECTORS") => {
+            let original = decode_records(entry, bytes, decode_sectors)?;
+            encode_if_changed(&map.sectors, &original, encode_sector)
+        }
+        (MapFormat::Udmf(udmf), "TEXTMAP") => {
+            check_udmf_unchanged(map, udmf, &bytes)?;
+            None
+        }
+        _ => None,
+    })
+}
+
+// Decoding loses some details, such as anything after the null that ends a name, so a lump is only
+// re-encoded if that gives a different result than re-encoding what was originally in it
+fn encode_if_changed<T>(
+    records: &[T],
+    original: &[T],
+    encoder_fn: fn(&T, &mut Vec<u8>),
+) -> Option<Vec<u8>> {
+    let encoded = encode_records(records, encoder_fn);
+    (encoded != encode_records(original, encoder_fn)).then_some(encoded)
+}
+
+fn unsavable(map: &MapData, message: &str) -> WadError {
+    WadError::UnsavableMapEdits {
+        map: map.name.clone(),
+        message: message.to_string(),
+    }
+}
+
+// Hexen maps have their things and linedefs twice: in Hexen's format in `MapFormat::Hexen`, and in
+// Doom's format in `MapData`. Either of them can have been edited, so for each field, whichever one
+// no longer matches the original lump wins. If both were changed, there's no telling which to keep.
+fn merge_field(generic: i16, hexen: i16, original: Option<i16>) -> Option<i16> {
+    if generic == hexen || original == Some(generic) {
+        Some(hexen)
+    } else if original == Some(hexen) {
+        Some(generic)
+    } else {
+        None
+    }
+}
+
+// Likewise, records can have been added or removed on one side or the other, but not both
+fn merged_len(generic: usize, hexen: usize, original: usize) -> Option<usize> {
+    if generic == hexen || hexen == original {
+        Some(generic)
+    } else if generic == original {
+        Some(hexen)
+    } else {
+        None
+    }
+}
+
+fn merge_hexen_things(
+    map: &MapData,
+    hexen_things: &[HexenThing],
+    original: &[HexenThing],
+) -> Result<Vec<HexenThing>, WadError> {
+    let conflict = || {
+        unsavable(
+            map,
+            "its things were changed in both its MapData and Hexen data",
+        )
+    };
+    let len =
+        merged_len(map.things.len(), hexen_things.len(), original.len()).ok_or_else(conflict)?;
+
+    (0..len)
+        .map(|i| {
+            let Some(thing) = map.things.get(i) else {
+                return Ok(hexen_things[i].clone());
+            };
+            // Things added to `MapData` start out with none of Hexen's extra fields set
+            let hexen_thing = hexen_things.get(i).cloned().unwrap_or(HexenThing {
+                tid: 0,
+                x: thing.x,
+                y: thing.y,
+                z: 0,
+                angle: thing.angle,
+                thing_type: thing.thing_type,
+                flags: thing.spawn_flags,
+                special: 0,
+                args: [0; 5],
+            });
+            let original = original.get(i);
+            let merge = |generic, hexen, field: fn(&HexenThing) -> i16| {
+                merge_field(generic, hexen, original.map(field)).ok_or_else(conflict)
+            };
+
+            Ok(HexenThing {
+                x: merge(thing.x, hexen_thing.x, |thing| thing.x)?,
+                y: merge(thing.y, hexen_thing.y, |thing| thing.y)?,
+                angle: merge(thing.angle, hexen_thing.angle, |thing| thing.angle)?,
+                thing_type: merge(thing.thing_type, hexen_thing.thing_type, |thing| {
+                    thing.thing_type
+                })?,
+                flags: merge(thing.spawn_flags, hexen_thing.flags, |thing| thing.flags)?,
+                ..hexen_thing
+            })
+        })
+        .collect()
+}
+
+fn merge_hexen_linedefs(
+    map: &MapData,
+    hexen_linedefs: &[HexenLineDef],
+    original: &[HexenLineDef],
+) -> Result<Vec<HexenLineDef>, WadError> {
+    let conflict = || {
+        unsavable(
+            map,
+            "its linedefs were changed in both its MapData and Hexen data",
+        )
+    };
+    let len = merged_len(map.linedefs.len(), hexen_linedefs.len(), original.len())
+        .ok_or_else(conflict)?;
+
+    (0..len)
+        .map(|i| {
+            let Some(linedef) = map.linedefs.get(i) else {
+                return Ok(hexen_linedefs[i].clone());
+            };
+            if linedef.sector_tag != 0 {
+                return Err(unsavable(
+                    map,
+                    &format!("linedef {} has a sector tag, which Hexen linedefs don't", i),
+                ));
+            }
+            // Linedefs added to `MapData` start out with no special arguments
+            let hexen_linedef = hexen_linedefs.get(i).cloned().unwrap_or(HexenLineDef {
+                vertex_begin: linedef.vertex_begin,
+                vertex_end: linedef.vertex_end,
+                flags: linedef.flags,
+                special: linedef.line_type as u8,
+                args: [0; 5],
+                sidedef_right: linedef.sidedef_right,
+                sidedef_left: linedef.sidedef_left,
+            });
+            let original = original.get(i);
+            let merge = |generic, hexen, field: fn(&HexenLineDef) -> i16| {
+                merge_field(generic, hexen, original.map(field)).ok_or_else(conflict)
+            };
+
+            let special = merge(linedef.line_type, hexen_linedef.special as i16, |linedef| {
+                linedef.special as i16
+            })?;
+            let special = u8::try_from(special).map_err(|_| {
+                unsavable(
+                    map,
+                    &format!(
+                        "linedef {} has special {}, but Hexen's only go up to 255",
+                        i, special
+                    ),
+                )
+            })?;
+
+            Ok(HexenLineDef {
+                vertex_begin: merge(
+                    linedef.vertex_begin,
+                    hexen_linedef.vertex_begin,
+                    |linedef| linedef.vertex_begin,
+                )?,
+                vertex_end: merge(linedef.vertex_end, hexen_linedef.vertex_end, |linedef| {
+                    linedef.vertex_end
+                })?,
+                flags: merge(linedef.flags, hexen_linedef.flags, |linedef| linedef.flags)?,
+                special,
+                sidedef_right: merge(
+                    linedef.sidedef_right,
+                    hexen_linedef.sidedef_right,
+                    |linedef| linedef.sidedef_right,
+                )?,
+                sidedef_left: merge(
+                    linedef.sidedef_left,
+                    hexen_linedef.sidedef_left,
+                    |linedef| linedef.sidedef_left,
+                )?,
+                ..hexen_linedef
+            })
+        })
+        .collect()
+}
+
+// There's no way to write out a TEXTMAP lump, so UDMF maps can only be saved as they were read
+fn check_udmf_unchanged(map: &MapData, udmf: &UdmfMap, bytes: &[u8]) -> Result<(), WadError> {
+    let original = UdmfMap::parse(&String::from_utf8_lossy(bytes))?;
+    let unchanged = *udmf == original
+        && map.linedefs == original.to_linedefs()
+        && map.sectors == original.to_sectors()
+        && map.sidedefs == original.to_sidedefs()
+        && map.things == original.to_things()
+        && map.vertexes == original.to_vertexes();
+    if !unchanged {
+        return Err(unsavable(
+            map,
+            "changes to UDMF maps can't be saved; replace their TEXTMAP lump instead",
+        ));
+    }
+    Ok(())
+}
+
+fn encode_records<T>(records: &[T], encoder_fn: fn(&T, &mut Vec<u8>)) -> Vec<u8> {
+    let mut buf = vec![];
+    for record in records {
+        encoder_fn(record, &mut buf);
+    }
+    buf
+}
+
+// Names are stored as 8 bytes, padded with nulls
+fn name_to_buf(name: &str) -> [u8; 8] {
+    let mut buf = [0; 8];
+    let bytes = name.as_bytes();
+    let len = bytes.len().min(8);
+    buf[..len].copy_from_slice(&bytes[..len]);
+    buf
+}
+
+fn texture_to_buf(texture: &Option<fixedstr::fstr<8>>) -> [u8; 8] {
+    const NO_TEXTURE_PLACEHOLDER: &str = "-";
+    name_to_buf(
+        texture
+            .as_ref()
+            .map_or(NO_TEXTURE_PLACEHOLDER, |name| name.to_str()),
+    )
+}
+
+fn encode_linedef(linedef: &LineDef, buf: &mut Vec<u8>) {
+    for int in [
+        linedef.vertex_begin,
+        linedef.vertex_end,
+        linedef.flags,
+        linedef.line_type,
+        linedef.sector_tag,
+        linedef.sidedef_right,
+        linedef.sidedef_left,
+    ] {
+        buf.extend_from_slice(&int.to_le_bytes());
+    }
+}
+
+fn encode_hexen_linedef(linedef: &HexenLineDef, buf: &mut Vec<u8>) {
+    buf.extend_from_slice(&linedef.vertex_begin.to_le_bytes());
+    buf.extend_from_slice(&linedef.vertex_end.to_le_bytes());
+    buf.extend_from_slice(&linedef.flags.to_le_bytes());
+    buf.push(linedef.special);
+    buf.extend_from_slice(&linedef.args);
+    buf.extend_from_slice(&linedef.sidedef_right.to_le_bytes());
+    buf.extend_from_slice(&linedef.sidedef_left.to_le_bytes());
+}
+
+fn encode_sector(sector: &Sector, buf: &mut Vec<u8>) {
+    buf.extend_from_slice(&sector.floor_height.to_le_bytes());
+    buf.extend_from_slice(&sector.ceiling_height.to_le_bytes());
+    buf.extend_from_slice(&name_to_buf(sector.floor_texture.to_str()));
+    buf.extend_from_slice(&name_to_buf(sector.ceiling_texture.to_str()));
+    buf.extend_from_slice(&sector.light_level.to_le_bytes());
+    buf.extend_from_slice(&sector.special.to_le_bytes());
+    buf.extend_from_slice(&sector.sector_tag.to_le_bytes());
+}
+
+fn encode_sidedef(sidedef: &SideDef, buf: &mut Vec<u8>) {
+    buf.extend_from_slice(&sidedef.x.to_le_bytes());
+    buf.extend_from_slice(&sidedef.y.to_le_bytes());
+    buf.extend_from_slice(&texture_to_buf(&sidedef.upper_texture));
+    buf.extend_from_slice(&texture_to_buf(&sidedef.lower_texture));
+    buf.extend_from_slice(&texture_to_buf(&sidedef.middle_texture));
+    buf.extend_from_slice(&sidedef.sector.to_le_bytes());
+}
+
+fn encode_thing(thing: &Thing, buf: &mut Vec<u8>) {
+    for int in [
+        thing.x,
+        thing.y,
+        thing.angle,
+        thing.thing_type,
+        thing.spawn_flags,
+    ] {
+        buf.extend_from_slice(&int.to_le_bytes());
+    }
+}
+
+fn encode_hexen_thing(thing: &HexenThing, buf: &mut Vec<u8>) {
+    for int in [
+        thing.tid,
+        thing.x,
+        thing.y,
+        thing.z,
+        thing.angle,
+        thing.thing_type,
+        thing.flags,
+    ] {
+        buf.extend_from_slice(&int.to_le_bytes());
+    }
+    buf.push(thing.special);
+    buf.extend_from_slice(&thing.args);
+}
+
+fn encode_vertex(vertex: &Vertex, buf: &mut Vec<u8>) {
+    buf.extend_from_slice(&vertex.x.to_le_bytes());
+    buf.extend_from_slice(&vertex.y.to_le_bytes());
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Lays the lumps out the usual way: data in directory order, then the directory
+    fn build_wad(lumps: &[(&str, Vec<u8>)]) -> Vec<u8> {
+        let data_size: usize = lumps.iter().map(|(_, data)| data.len()).sum();
+        let mut bytes = b"PWAD".to_vec();
+        bytes.extend_from_slice(&(lumps.len() as i32).to_le_bytes());
+        bytes.extend_from_slice(&(12 + data_size as i32).to_le_bytes());
+        for (_, data) in lumps {
+            bytes.extend_from_slice(data);
+        }
+
+        let mut offset = 12;
+        for (name, data) in lumps {
+            bytes.extend_from_slice(&(offset as i32).to_le_bytes());
+            bytes.extend_from_slice(&(data.len() as i32).to_le_bytes());
+            bytes.extend_from_slice(&name_to_buf(name));
+            offset += data.len();
+        }
+        bytes
+    }
+
+    fn ints(ints: &[i16]) -> Vec<u8> {
+        ints.iter().flat_map(|int| int.to_le_bytes()).collect()
+    }
+
+    fn sector(floor_texture: &[u8; 8]) -> Vec<u8> {
+        let mut buf = ints(&[0, 128]);
+        buf.extend_from_slice(floor_texture);
+        buf.extend_from_slice(b"CEIL\0\0\0\0");
+        buf.extend_from_slice(&ints(&[160, 0, 0]));
+        buf
+    }
+
+    fn sidedef() -> Vec<u8> {
+        let mut buf = ints(&[0, 0]);
+        buf.extend_from_slice(b"-\0\0\0\0\0\0\0-\0\0\0\0\0\0\0STARTAN3");
+        buf.extend_from_slice(&ints(&[0]));
+        buf
+    }
+
+    fn doom_wad(floor_texture: &[u8; 8]) -> Vec<u8> {
+        build_wad(&[
+            ("MAP01", vec![]),
+            ("THINGS", ints(&[32, 64, 90, 1, 7])),
+            ("LINEDEFS", ints(&[0, 1, 1, 0, 0, 0, -1])),
+            ("SIDEDEFS", sidedef()),
+            ("VERTEXES", ints(&[0, 0, 64, 0])),
+            ("SECTORS", sector(floor_texture)),
+            ("README", b"not a map lump".to_vec()),
+        ])
+    }
+
+    fn hexen_wad() -> Vec<u8> {
+        let mut linedef = ints(&[0, 1, 1]);
+        linedef.extend_from_slice(&[80, 1, 2, 3, 4, 5]);
+        linedef.extend_from_slice(&ints(&[0, -1]));
+        let mut thing = ints(&[5, 32, 64, 16, 90, 1, 7]);
+        thing.extend_from_slice(&[0, 0, 0, 0, 0, 0]);
+
+        build_wad(&[
+            ("MAP01", vec![]),
+            ("THINGS", thing),
+            ("LINEDEFS", linedef),
+            ("SIDEDEFS", sidedef()),
+            ("VERTEXES", ints(&[0, 0, 64, 0])),
+            ("SECTORS", sector(b"FLAT\0\0\0\0")),
+            ("BEHAVIOR", b"ACS\0".to_vec()),
+        ])
+    }
+
+    fn save(wad: &Wad) -> Result<Vec<u8>, WadError> {
+        let mut bytes = vec![];
+        wad.save(&mut bytes)?;
+        Ok(bytes)
+    }
+
+    #[test]
+    fn unmodified_wad_is_saved_byte_for_byte() {
+        for bytes in [doom_wad(b"FLAT\0\0\0\0"), hexen_wad()] {
+            let wad = Wad::from_bytes(&bytes).unwrap();
+            assert_eq!(wad.maps().len(), 1);
+            assert_eq!(save(&wad).unwrap(), bytes);
+        }
+    }
+
+    #[test]
+    fn junk_after_names_survives_when_the_map_is_unmodified() {
+        let bytes = doom_wad(b"FLAT\0XYZ");
+        let mut wad = Wad::from_bytes(&bytes).unwrap();
+        assert_eq!(wad.maps()[0].sectors[0].floor_texture, "FLAT");
+        assert_eq!(save(&wad).unwrap(), bytes);
+
+        // Changing another lump in the map leaves SECTORS alone
+        wad.maps_mut()[0].things[0].x = 1234;
+        let saved = Wad::from_bytes(&save(&wad).unwrap()).unwrap();
+        let sectors = saved.find_lump("SECTORS").unwrap();
+        assert_eq!(saved.lump_bytes(sectors).unwrap(), sector(b"FLAT\0XYZ"));
+    }
+
+    #[test]
+    fn doom_map_edits_are_saved() {
+        let mut wad = Wad::from_bytes(&doom_wad(b"FLAT\0\0\0\0")).unwrap();
+        let map = &mut wad.maps_mut()[0];
+        map.things[0].x = 1234;
+        map.linedefs[0].sector_tag = 9;
+        map.sectors[0].floor_texture = "NUKAGE1".into();
+
+        let saved = Wad::from_bytes(&save(&wad).unwrap()).unwrap();
+        let map = &saved.maps()[0];
+        assert_eq!(map.things[0].x, 1234);
+        assert_eq!(map.linedefs[0].sector_tag, 9);
+        assert_eq!(map.sectors[0].floor_texture, "NUKAGE1");
+    }
+
+    #[test]
+    fn replaced_map_lumps_are_decoded_and_can_still_be_edited() {
+        let mut wad = Wad::from_bytes(&doom_wad(b"FLAT\0\0\0\0")).unwrap();
+        wad.maps_mut()[0].sectors[0].floor_texture = "NUKAGE1".into();
+        let things = wad.find_lump_index("THINGS").unwrap();
+        wad.replace_lump(things, ints(&[32, 64, 90, 3004, 7]))
+            .unwrap();
+        assert_eq!(wad.maps()[0].things[0].thing_type, 3004);
+        assert_eq!(wad.maps()[0].sectors[0].floor_texture, "NUKAGE1");
+
+        wad.maps_mut()[0].things[0].x = 1234;
+        let saved = Wad::from_bytes(&save(&wad).unwrap()).unwrap();
+        let map = &saved.maps()[0];
+        assert_eq!((map.things[0].x, map.things[0].thing_type), (1234, 3004));
+        assert_eq!(map.sectors[0].floor_texture, "NUKAGE1");
+    }
+
+    #[test]
+    fn renaming_a_map_over_another_keeps_their_lumps_apart() {
+        let mut lumps = vec![];
+        for (map_name, thing_type) in [("MAP01", 1), ("MAP02", 2)] {
+            lumps.extend([
+                (map_name, vec![]),
+                ("THINGS", ints(&[32, 64, 90, thing_type, 7])),
+                ("LINEDEFS", ints(&[0, 1, 1, 0, 0, 0, -1])),
+                ("SIDEDEFS", sidedef()),
+                ("VERTEXES", ints(&[0, 0, 64, 0])),
+                ("SECTORS", sector(b"FLAT\0\0\0\0")),
+            ]);
+        }
+        let mut wad = Wad::from_bytes(&build_wad(&lumps)).unwrap();
+        wad.maps_mut()[0].things[0].x = 1234;
+        wad.rename_lump(6, "MAP01").unwrap();
+
+        // Like Doom, the last map with a given name is the one that's used
+        assert_eq!(wad.maps().len(), 1);
+        assert_eq!(wad.maps()[0].things[0].thing_type, 2);
+        wad.maps_mut()[0].things[0].y = 5678;
+
+        let saved = Wad::from_bytes(&save(&wad).unwrap()).unwrap();
+        let things = |index| saved.lump_bytes(&saved.directory[index]).unwrap();
+        assert_eq!(things(1), ints(&[1234, 64, 90, 1, 7]));
+        assert_eq!(things(7), ints(&[32, 5678, 90, 2, 7]));
+    }
+
+    #[test]
+    fn hexen_map_edits_are_saved() {
+        let mut wad = Wad::from_bytes(&hexen_wad()).unwrap();
+        let map = &mut wad.maps_mut()[0];
+        map.things[0].x = 1234;
+        map.linedefs[0].line_type = 12;
+        let MapFormat::Hexen { things, .. } = &mut map.format else {
+            panic!("expected a Hexen map");
+        };
+        things[0].tid = 42;
+
+        let saved = Wad::from_bytes(&save(&wad).unwrap()).unwrap();
+        let map = &saved.maps()[0];
+        assert_eq!(map.things[0].x, 1234);
+        assert_eq!(map.linedefs[0].line_type, 12);
+        let MapFormat::Hexen {
+            things, linedefs, ..
+        } = &map.format
+        else {
+            panic!("expected a Hexen map");
+        };
+        assert_eq!((things[0].tid, things[0].x), (42, 1234));
+        assert_eq!(
+            (linedefs[0].special, linedefs[0].args),
+            (12, [1, 2, 3, 4, 5])
+        );
+    }
+
+    #[test]
+    fn conflicting_hexen_edits_are_an_error() {
+        let mut wad = Wad::from_bytes(&hexen_wad()).unwrap();
+        let map = &mut wad.maps_mut()[0];
+        map.things[0].x = 1234;
+        let MapFormat::Hexen { things, .. } = &mut map.format else {
+            panic!("expected a Hexen map");
+        };
+        things[0].x = 5678;
+
+        assert!(matches!(
+            save(&wad),
+            Err(WadError::UnsavableMapEdits { .. })
+        ));
+    }
+
+    #[test]
+    fn udmf_edits_are_an_error() {
+        let textmap = b"namespace = \"doom\";\
+            vertex { x = 0; y = 0; }\
+            vertex { x = 64; y = 0; }\
+            sector { texturefloor = \"FLAT\"; textureceiling = \"CEIL\"; }\
+            sidedef { sector = 0; }\
+            linedef { v1 = 0; v2 = 1; sidefront = 0; }\
+            thing { x = 32; y = 64; type = 1; }";
+        let bytes = build_wad(&[
+            ("MAP01", vec![]),
+            ("TEXTMAP", textmap.to_vec()),
+            ("ENDMAP", vec![]),
+        ]);
+        let mut wad = Wad::from_bytes(&bytes).unwrap();
+        assert_eq!(save(&wad).unwrap(), bytes);
+
+        wad.maps_mut()[0].things[0].x = 1234;
+        assert!(matches!(
+            save(&wad),
+            Err(WadError::UnsavableMapEdits { .. })
+        ));
+    }
+}